@@ -4,13 +4,83 @@ pub const MAX_BUILDINGS: u8 = 20;
 pub const MAX_UPGRADED_TILES: u8 = 100;
 pub const MAX_PRODUCTION_QUEUE: u8 = 5;
 pub const MAP_BOUND: u8 = 20;
+pub const MAX_PLAYERS: u8 = 8;
+pub const MAX_ACTIVE_GAMES: u8 = 10;
+// every pair of players can have its own relation
+pub const MAX_RELATIONS: u8 = MAX_PLAYERS * (MAX_PLAYERS - 1) / 2;
+/// seconds a player has to end their turn before the other players can skip it
+pub const TURN_TIMEOUT: i64 = 24 * 60 * 60;
 
 pub const GEMS_PER_KILL: [u8; 3] = [1, 1, 2];
 pub const GEMS_PER_CITY_DESTROYED: [u8; 3] = [25, 50, 100];
+pub const CAPTURED_CITY_HEALTH: u32 = 25;
 
 pub const EXP_THRESHOLDS: [u8; 3] = [10, 30, 45];
 pub const EXP_PER_ATTACK: u8 = 3;
+pub const LEVEL_UP_HEAL: u8 = 30;
+
+pub const PROMOTION_STRENGTH_BONUS: u8 = 2;
+pub const PROMOTION_COVER_BONUS: u8 = 3;
+pub const PROMOTION_KILL_HEAL: u8 = 30;
+
+/// share of the successor gold cost paid to upgrade a unit, in percent
+pub const UNIT_UPGRADE_COST_PERCENT: u32 = 50;
+/// share of the unit resource or production cost returned when the unit is disbanded, in percent
+pub const DISBAND_REFUND_PERCENT: u32 = 50;
+// combat random factor is drawn from the range [0, COMBAT_RANDOM_RANGE)
+pub const COMBAT_RANDOM_RANGE: u64 = 10;
 pub const SPAWN_INTERVAL: [u8; 3] = [20, 15, 10];
+// max Chebyshev distance between attacker and target
+pub const MELEE_ATTACK_RANGE: u8 = 1;
+pub const RANGED_ATTACK_RANGE: u8 = 2;
+pub const CITY_STRIKE_RANGE: u8 = 2;
+// combat strength lost by a unit attacking across a river
+pub const RIVER_CROSSING_PENALTY: u8 = 3;
+// siege units are built to destroy cities and are weak against other units
+pub const SIEGE_CITY_BONUS: u8 = 10;
+pub const SIEGE_UNIT_PENALTY: u8 = 10;
+
+// food spent on a single heal_unit and HP restored depending on where the unit stays
+pub const HEAL_FOOD_COST: u32 = 10;
+pub const HEAL_AMOUNT_FIELD: u8 = 15;
+pub const HEAL_AMOUNT_TERRITORY: u8 = 25;
+pub const HEAL_AMOUNT_BARRACKS: u8 = 40;
+
+pub const FORTIFY_DEFENSE_BONUS: u8 = 3;
+pub const MAX_FORTIFY_TURNS: u8 = 2;
+// the highest bit of a map tile code marks a river, the lower bits are the terrain type
+pub const RIVER_FLAG: u8 = 0x80;
+
+// combat math is done in fixed point, 1.0 == FIXED_POINT_SCALE
+pub const FIXED_POINT_SCALE: u128 = 1_000_000;
+// strength differences beyond this value are capped, damage is clamped to 255 long before that
+pub const MAX_STRENGTH_DIFF: i32 = 150;
+// e^k for k in [0, MAX_STRENGTH_DIFF / 25]
+pub const EXP_WHOLE_TABLE: [u128; 7] = [
+    1_000_000,
+    2_718_282,
+    7_389_056,
+    20_085_537,
+    54_598_150,
+    148_413_159,
+    403_428_793,
+];
+// e^(r / 25) for r in [0, 24]
+pub const EXP_FRACTION_TABLE: [u128; 25] = [
+    1_000_000, 1_040_811, 1_083_287, 1_127_497, 1_173_511, 1_221_403, 1_271_249, 1_323_130,
+    1_377_128, 1_433_329, 1_491_825, 1_552_707, 1_616_074, 1_682_028, 1_750_673, 1_822_119,
+    1_896_481, 1_973_878, 2_054_433, 2_138_276, 2_225_541, 2_316_367, 2_410_900, 2_509_290,
+    2_611_696,
+];
+// random damage multiplier is DAMAGE_MULTIPLIER_BASE + random_factor * DAMAGE_MULTIPLIER_STEP
+pub const DAMAGE_MULTIPLIER_BASE: u128 = 900_000;
+pub const DAMAGE_MULTIPLIER_STEP: u128 = 22_300;
+
+// max number of turns a session key can act on behalf of the player
+pub const MAX_SESSION_TURNS: u32 = 100;
 
 // default storage capacity per strategic resource
 pub const STORAGE_CAPACITY: u8 = 50;
+// default capacity of the player's food stockpile, every Granary adds GRANARY_FOOD_CAPACITY
+pub const FOOD_STORAGE_CAPACITY: u32 = 100;
+pub const GRANARY_FOOD_CAPACITY: u32 = 50;
@@ -44,8 +44,23 @@ pub enum UnitError {
     #[msg("Not enought experience to level up unit")]
     NotEnoughExp,
 
+    #[msg("Promotion is not available for this unit")]
+    InvalidPromotion,
+
+    #[msg("Unit type cannot be upgraded")]
+    NoUpgradeAvailable,
+
+    #[msg("Unit has to be inside own territory")]
+    NotInOwnTerritory,
+
     #[msg("Cannot build a city on this tile")]
     WithinControlledTerritory,
+
+    #[msg("Units cannot move through this terrain")]
+    ImpassableTerrain,
+
+    #[msg("Cities cannot be founded on this terrain")]
+    NotBuildable,
 }
 
 #[error_code]
@@ -112,6 +127,12 @@ pub enum CityError {
 
     #[msg("Not enough citizens to recruit a Settler")]
     InsufficientPopulationForSettler,
+
+    #[msg("City has already struck this turn")]
+    AlreadyStruck,
+
+    #[msg("City is not occupied")]
+    NotOccupied,
 }
 
 #[error_code]
@@ -134,4 +155,79 @@ pub enum ResearchError {
 pub enum GameError {
     #[msg("Not enough gems")]
     NotEnoughGems,
+
+    #[msg("Game is already over")]
+    GameOver,
+
+    #[msg("Game is full")]
+    GameFull,
+
+    #[msg("Player already joined the game")]
+    AlreadyJoined,
+
+    #[msg("Player is not part of the game")]
+    NotInGame,
+
+    #[msg("It is not your turn")]
+    NotYourTurn,
+
+    #[msg("The current player still has time to end their turn")]
+    TurnNotTimedOut,
+
+    #[msg("Only the host can perform this action")]
+    NotHost,
+
+    #[msg("Host cannot leave the game, close it instead")]
+    HostCannotLeave,
+
+    #[msg("Other players are still in the game")]
+    PlayersStillJoined,
+
+    #[msg("Accounts of the other players are missing or invalid")]
+    InvalidPlayerAccounts,
+
+    #[msg("Too many active games")]
+    TooManyActiveGames,
+
+    #[msg("Recent slot hashes are not available")]
+    SlotHashesUnavailable,
+
+    #[msg("Another action is waiting to be resolved")]
+    CommitmentPending,
+
+    #[msg("Committed action can only be resolved in a later slot")]
+    CommitmentNotReady,
+
+    #[msg("Map contains an unknown terrain type")]
+    InvalidTerrain,
+}
+
+#[error_code]
+pub enum DiplomacyError {
+    #[msg("Invalid civilization")]
+    InvalidCivilization,
+
+    #[msg("Civilizations are not at war")]
+    NotAtWar,
+
+    #[msg("Civilizations are already at war")]
+    AlreadyAtWar,
+
+    #[msg("Civilizations are not at peace")]
+    NotAtPeace,
+
+    #[msg("Civilizations are not allies")]
+    NotAllies,
+
+    #[msg("No matching proposal from this civilization")]
+    NoProposal,
+}
+
+#[error_code]
+pub enum SessionError {
+    #[msg("Signer is not authorized to act on behalf of the player")]
+    InvalidSigner,
+
+    #[msg("Invalid session duration")]
+    InvalidDuration,
 }
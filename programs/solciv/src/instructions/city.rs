@@ -1,7 +1,9 @@
 use crate::consts::*;
 use crate::errors::*;
 use crate::state::*;
+use crate::utils::*;
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::slot_hashes;
 
 pub fn add_to_production_queue(
     ctx: Context<AddToProductionQueue>,
@@ -59,7 +61,7 @@ pub fn add_to_production_queue(
                     // @todo: decrease population when the settler is recruited
                     0
                 }
-                UnitType::Swordsman => Unit::get_resource_cost(*unit_type),
+                UnitType::Swordsman | UnitType::Pikeman => Unit::get_resource_cost(*unit_type),
                 UnitType::Horseman => Unit::get_resource_cost(*unit_type),
                 _ => 0, // No resource cost for other unit types
             }
@@ -69,7 +71,9 @@ pub fn add_to_production_queue(
     // Perform the necessary deductions
     if total_cost > 0 {
         let resource_type = match &item {
-            ProductionItem::Unit(UnitType::Swordsman) => &mut player_account.resources.iron,
+            ProductionItem::Unit(UnitType::Swordsman | UnitType::Pikeman) => {
+                &mut player_account.resources.iron
+            }
             ProductionItem::Unit(UnitType::Horseman) => &mut player_account.resources.horses,
             // can this really happen?
             _ => return err!(CityError::InvalidItem),
@@ -121,6 +125,41 @@ pub fn remove_from_production_queue(
     Ok(())
 }
 
+pub fn annex_city(ctx: Context<AnnexCity>, city_id: u32) -> Result<()> {
+    let city = ctx
+        .accounts
+        .player_account
+        .cities
+        .iter_mut()
+        .find(|city| city.city_id == city_id)
+        .ok_or(CityError::CityNotFound)?;
+
+    if city.status != CityStatus::Occupied {
+        return err!(CityError::NotOccupied);
+    }
+    city.status = CityStatus::Normal;
+
+    Ok(())
+}
+
+pub fn raze_city(ctx: Context<RazeCity>, city_id: u32) -> Result<()> {
+    let city = ctx
+        .accounts
+        .player_account
+        .cities
+        .iter_mut()
+        .find(|city| city.city_id == city_id)
+        .ok_or(CityError::CityNotFound)?;
+
+    if city.status != CityStatus::Occupied {
+        return err!(CityError::NotOccupied);
+    }
+    // the city is removed in end_turn once it has no citizens left
+    city.status = CityStatus::Razing;
+
+    Ok(())
+}
+
 pub fn repair_wall(ctx: Context<RepairWall>, city_id: u32) -> Result<()> {
     let player_account: &mut Account<'_, Player> = &mut ctx.accounts.player_account;
 
@@ -201,6 +240,13 @@ pub fn purchase_with_gold(
     // Deduct the cost from the player's gold balance.
     player_account.resources.gold -= cost;
 
+    // Settler is paid with food from the stockpile if there is enough, otherwise with a citizen
+    let settler_paid_with_food = item == ProductionItem::Unit(UnitType::Settler)
+        && player_account.resources.food >= Unit::get_resource_cost(UnitType::Settler);
+    if settler_paid_with_food {
+        player_account.resources.food -= Unit::get_resource_cost(UnitType::Settler);
+    }
+
     // Find the city by city_id.
     let city = player_account
         .cities
@@ -209,7 +255,7 @@ pub fn purchase_with_gold(
         .ok_or(CityError::CityNotFound)?;
 
     // Settler has special conditions as it consumes 1 Citizen, so city population should be at least 2
-    if let ProductionItem::Unit(UnitType::Settler) = &item {
+    if item == ProductionItem::Unit(UnitType::Settler) && !settler_paid_with_food {
         if city.population < 2 {
             return err!(CityError::InsufficientPopulationForSettler);
         } else {
@@ -250,9 +296,112 @@ pub fn purchase_with_gold(
     Ok(())
 }
 
+pub fn city_strike(ctx: Context<CityStrike>, city_id: u32, unit_id: u32) -> Result<()> {
+    // barbarians are targeted via npc_account, other civilizations via defender_account
+    if let Some(defender_account) = &ctx.accounts.defender_account {
+        if !ctx.accounts.game.is_at_war(
+            &ctx.accounts.player_account.player,
+            &defender_account.player,
+        ) {
+            return err!(DiplomacyError::NotAtWar);
+        }
+    }
+    let random_factor = draw_combat_random_factor(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[
+            b"city_strike",
+            &city_id.to_le_bytes(),
+            &unit_id.to_le_bytes(),
+        ],
+        &ctx.accounts.recent_slothashes,
+    )?;
+
+    let city = ctx
+        .accounts
+        .player_account
+        .cities
+        .iter_mut()
+        .find(|city| city.city_id == city_id)
+        .ok_or(CityError::CityNotFound)?;
+
+    if city.wall_health == 0 {
+        return err!(CityError::NoWall);
+    }
+    if !city.can_strike() {
+        return err!(CityError::AlreadyStruck);
+    }
+
+    let target_units = match (
+        ctx.accounts.npc_account.as_mut(),
+        ctx.accounts.defender_account.as_mut(),
+    ) {
+        (Some(npc_account), _) => &mut npc_account.units,
+        (None, Some(defender_account)) => &mut defender_account.units,
+        (None, None) => return err!(UnitError::UnitNotFound),
+    };
+    let target = target_units
+        .iter_mut()
+        .find(|u| u.unit_id == unit_id && u.is_alive)
+        .ok_or(UnitError::UnitNotFound)?;
+
+    if chebyshev_distance(city.x, city.y, target.x, target.y) > CITY_STRIKE_RANGE {
+        return err!(UnitError::OutOfAttackRange);
+    }
+
+    // the strike is committed first and resolved in a later slot
+    let Some(random_factor) = random_factor else {
+        return Ok(());
+    };
+    let damage = city.strike_damage(target, random_factor);
+    target.apply_damage(damage);
+    city.has_struck = true;
+    msg!("City strike damage: {}", damage);
+
+    if !target.is_alive {
+        ctx.accounts.player_account.resources.gems = ctx
+            .accounts
+            .player_account
+            .resources
+            .gems
+            .saturating_add(GEMS_PER_KILL[ctx.accounts.game.difficulty_level as usize] as u32);
+        target_units.retain(|u| u.is_alive);
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct AnnexCity<'info> {
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RazeCity<'info> {
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct RepairWall<'info> {
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -260,15 +409,26 @@ pub struct RepairWall<'info> {
 
 #[derive(Accounts)]
 pub struct AddToProductionQueue<'info> {
-    #[account(mut, has_one = player)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
+    pub session_token: Option<Account<'info, SessionToken>>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
 
 #[derive(Accounts)]
 pub struct RemoveFromProductionQueue<'info> {
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -276,8 +436,39 @@ pub struct RemoveFromProductionQueue<'info> {
 
 #[derive(Accounts)]
 pub struct PurchaseWithGold<'info> {
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CityStrike<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
     pub player_account: Account<'info, Player>,
+    #[account(mut, has_one = game)]
+    pub npc_account: Option<Account<'info, Npc>>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = defender_account.key() != player_account.key() @ UnitError::InvalidAttack
+    )]
+    pub defender_account: Option<Account<'info, Player>>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
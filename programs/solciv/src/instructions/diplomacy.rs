@@ -0,0 +1,153 @@
+use crate::errors::*;
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+fn validate_civilization(game: &Game, player: &Pubkey, civilization: &Pubkey) -> Result<()> {
+    if player == civilization || !game.has_player(civilization) {
+        return err!(DiplomacyError::InvalidCivilization);
+    }
+    Ok(())
+}
+
+pub fn declare_war(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    validate_civilization(&ctx.accounts.game, &player, &civilization)?;
+
+    let relation = ctx.accounts.game.relation_mut(&player, &civilization);
+    if relation.status == DiplomaticStatus::War {
+        return err!(DiplomacyError::AlreadyAtWar);
+    }
+
+    // declaring war breaks any alliance and cancels pending proposals
+    relation.status = DiplomaticStatus::War;
+    relation.proposal = None;
+
+    msg!("War declared!");
+
+    Ok(())
+}
+
+pub fn propose_peace(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    validate_civilization(&ctx.accounts.game, &player, &civilization)?;
+
+    if !ctx.accounts.game.is_at_war(&player, &civilization) {
+        return err!(DiplomacyError::NotAtWar);
+    }
+
+    let relation = ctx.accounts.game.relation_mut(&player, &civilization);
+    relation.proposal = Some(Proposal {
+        proposed_by: player,
+        status: DiplomaticStatus::Peace,
+    });
+
+    Ok(())
+}
+
+pub fn accept_peace(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    validate_civilization(&ctx.accounts.game, &player, &civilization)?;
+
+    let relation = ctx.accounts.game.relation_mut(&player, &civilization);
+    match relation.proposal {
+        Some(proposal)
+            if proposal.proposed_by == civilization
+                && proposal.status == DiplomaticStatus::Peace => {}
+        _ => return err!(DiplomacyError::NoProposal),
+    }
+
+    relation.status = DiplomaticStatus::Peace;
+    relation.proposal = None;
+
+    msg!("Peace treaty signed!");
+
+    Ok(())
+}
+
+pub fn propose_alliance(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    validate_civilization(&ctx.accounts.game, &player, &civilization)?;
+
+    if ctx.accounts.game.diplomatic_status(&player, &civilization) != DiplomaticStatus::Peace {
+        return err!(DiplomacyError::NotAtPeace);
+    }
+
+    let relation = ctx.accounts.game.relation_mut(&player, &civilization);
+    relation.proposal = Some(Proposal {
+        proposed_by: player,
+        status: DiplomaticStatus::Alliance,
+    });
+
+    Ok(())
+}
+
+pub fn accept_alliance(ctx: Context<AllianceVision>) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    let civilization = ctx.accounts.ally_account.player;
+    validate_civilization(&ctx.accounts.game, &player, &civilization)?;
+
+    let relation = ctx.accounts.game.relation_mut(&player, &civilization);
+    match relation.proposal {
+        Some(proposal)
+            if proposal.proposed_by == civilization
+                && proposal.status == DiplomaticStatus::Alliance => {}
+        _ => return err!(DiplomacyError::NoProposal),
+    }
+
+    relation.status = DiplomaticStatus::Alliance;
+    relation.proposal = None;
+
+    ctx.accounts
+        .player_account
+        .exchange_maps(&mut ctx.accounts.ally_account);
+
+    msg!("Alliance formed!");
+
+    Ok(())
+}
+
+pub fn exchange_maps(ctx: Context<AllianceVision>) -> Result<()> {
+    let player = ctx.accounts.player_account.player;
+    let civilization = ctx.accounts.ally_account.player;
+
+    if !ctx.accounts.game.are_allies(&player, &civilization) {
+        return err!(DiplomacyError::NotAllies);
+    }
+
+    ctx.accounts
+        .player_account
+        .exchange_maps(&mut ctx.accounts.ally_account);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateRelations<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        has_one = player,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AllianceVision<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        mut,
+        has_one = player,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut, has_one = game)]
+    pub ally_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
@@ -1,6 +1,11 @@
 use crate::consts::*;
+use crate::errors::*;
 use crate::state::*;
+use crate::utils::{
+    chebyshev_distance, load_other_players, reveal_randomness, tile_defender_index,
+};
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::slot_hashes;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 
@@ -9,17 +14,34 @@ pub fn initialize_game(
     map: [u8; 400],
     difficulty_level: u8,
 ) -> Result<()> {
+    let game_key = ctx.accounts.game.key();
+    let registry = &mut ctx.accounts.registry;
+    registry.player = ctx.accounts.player.key();
+    registry.add_game(game_key)?;
+    ctx.accounts.game.game_id = registry.next_game_id;
+    registry.next_game_id += 1;
+
     ctx.accounts.game.player = ctx.accounts.player.key();
     ctx.accounts.game.turn = 1;
     ctx.accounts.game.defeat = false;
     ctx.accounts.game.victory = false;
     ctx.accounts.game.difficulty_level = difficulty_level;
+    ctx.accounts.game.players = vec![];
+    ctx.accounts.game.current_player_index = 0;
+    ctx.accounts.game.turn_started_at = Clock::get()?.unix_timestamp;
+    ctx.accounts.game.relations = vec![];
+    let slot = Clock::get()?.slot;
+    ctx.accounts.game.randomness = Randomness::new(&[
+        game_key.as_ref(),
+        ctx.accounts.player.key().as_ref(),
+        &slot.to_le_bytes(),
+    ]);
 
     for i in 0..20 {
         for j in 0..20 {
             let index = i * 20 + j;
-            ctx.accounts.game.map[index].terrain = map[index];
-            ctx.accounts.game.map[index].discovered = false;
+            ctx.accounts.game.map[index].terrain = TerrainType::try_from(map[index] & !RIVER_FLAG)?;
+            ctx.accounts.game.map[index].river = map[index] & RIVER_FLAG != 0;
         }
     }
 
@@ -31,13 +53,17 @@ pub fn initialize_game(
 fn heal_units_and_reset_movement_range(units: &mut [Unit]) {
     for unit in units.iter_mut().filter(|u| u.is_alive) {
         // Heal if the unit did not move/attack and has less than max HP
-        if unit.health < 100 && unit.movement_range == Unit::get_base_movement_range(unit.unit_type)
-        {
+        if unit.health < 100 && unit.movement_range == unit.get_movement_range() {
             unit.health = std::cmp::min(unit.health + 5, 100);
         }
 
+        // Fortified units dig in further every turn
+        if unit.stance == UnitStance::Fortified && unit.fortify_turns < MAX_FORTIFY_TURNS {
+            unit.fortify_turns += 1;
+        }
+
         // Reset movement range
-        unit.movement_range = Unit::get_base_movement_range(unit.unit_type);
+        unit.movement_range = unit.get_movement_range();
     }
 }
 
@@ -82,14 +108,23 @@ fn is_within_bounds(coord: &TileCoordinate) -> bool {
     coord.x < MAP_BOUND && coord.y < MAP_BOUND
 }
 
-fn calculate_resources(player_account: &Player) -> (i32, u32, u32, u32, u32, u32) {
+fn calculate_resources(player_account: &Player) -> (i32, u32, u32, u32, u32, u32, u32) {
     // Calculate resources yielded by cities and tiles.
-    // This function will return a tuple (gold, wood, stone, iron, horses, science).
-    let mut resources: (i32, u32, u32, u32, u32, u32) = (0, 0, 0, 0, 0, 0);
+    // This function will return a tuple (gold, wood, stone, iron, horses, food, science).
+    let mut resources: (i32, u32, u32, u32, u32, u32, u32) = (0, 0, 0, 0, 0, 0, 0);
 
-    for city in &player_account.cities {
+    // a city being razed yields nothing
+    for city in player_account
+        .cities
+        .iter()
+        .filter(|c| c.status != CityStatus::Razing)
+    {
         resources.0 += city.gold_yield as i32;
-        resources.5 += city.science_yield;
+        resources.6 += city.science_yield;
+        // a city that cannot grow anymore sends its food surplus to the player's stockpile
+        if city.population >= city.housing {
+            resources.5 += city.food_surplus().max(0) as u32;
+        }
     }
 
     for tile in &player_account.tiles {
@@ -116,9 +151,10 @@ fn calculate_resources(player_account: &Player) -> (i32, u32, u32, u32, u32, u32
 fn process_production_queues(player_account: &mut Player, game_key: Pubkey) -> Result<()> {
     let mut new_units = Vec::new();
     let mut next_unit_id = player_account.next_unit_id;
+    let mut food_stockpile = player_account.resources.food;
     let player = player_account.player;
 
-    for city in &mut player_account.cities {
+    for city in player_account.cities.iter_mut().filter(|c| c.can_produce()) {
         if let Some(item) = city.production_queue.first().cloned() {
             let cost = match item {
                 ProductionItem::Unit(unit_type) => Unit::get_base_stats(unit_type).5,
@@ -134,9 +170,15 @@ fn process_production_queues(player_account: &mut Player, game_key: Pubkey) -> R
                 // Production completed
                 match item {
                     ProductionItem::Unit(unit_type) => {
-                        // If the unit is a Settler and the city population is greater than 1, decrease the population
-                        if unit_type == UnitType::Settler && city.population > 1 {
-                            city.population -= 1;
+                        // A Settler is paid with food from the stockpile if there is enough,
+                        // otherwise if the city population is greater than 1, decrease the population
+                        if unit_type == UnitType::Settler {
+                            let food_cost = Unit::get_resource_cost(unit_type);
+                            if food_stockpile >= food_cost {
+                                food_stockpile -= food_cost;
+                            } else if city.population > 1 {
+                                city.population -= 1;
+                            }
                         }
                         // Create a new unit and add it to the player's units
                         let new_unit =
@@ -157,19 +199,31 @@ fn process_production_queues(player_account: &mut Player, game_key: Pubkey) -> R
     }
 
     player_account.units.append(&mut new_units);
+    player_account.resources.food = food_stockpile;
     player_account.next_unit_id = next_unit_id;
 
     Ok(())
 }
 
 fn process_npc_movements_and_attacks(
-    npc_units: &mut Vec<Unit>,
-    player: &mut Player,
+    npc: &mut Npc,
+    players: &mut [&mut Player],
+    map: &[Terrain],
     difficulty_level: u8,
+    randomness: &mut Randomness,
 ) -> Result<()> {
+    let Npc {
+        units: npc_units,
+        cities: npc_cities,
+        player: npc_key,
+        next_unit_id,
+        next_city_id,
+        ..
+    } = npc;
+    // captured civilians are appended to the end and stay in place
     let npc_units_count = npc_units.len();
     for i in 0..npc_units_count {
-        if !npc_units[i].is_alive {
+        if !npc_units[i].is_alive || npc_units[i].is_civilian() {
             continue;
         }
 
@@ -177,10 +231,8 @@ fn process_npc_movements_and_attacks(
         if npc_units[i].level < EXP_THRESHOLDS.len() as u8
             && npc_units[i].experience >= EXP_THRESHOLDS[npc_units[i].level as usize]
         {
-            npc_units[i].level += 1;
-            npc_units[i].attack += 2;
-            npc_units[i].health = std::cmp::min(npc_units[i].health + 30, 100);
-            npc_units[i].movement_range = 0;
+            // barbarians always choose the strength bonus
+            npc_units[i].promote(Promotion::Strength)?;
 
             msg!(
                 "NPC unit #{} leveled up to level {}",
@@ -192,35 +244,40 @@ fn process_npc_movements_and_attacks(
         }
 
         let mut min_dist = u16::MAX;
-        let mut closest_target: Option<(u8, u8)> = None;
-
-        // Find the closest player's unit or city to the NPC unit
-        for player_unit in player.units.iter().filter(|u| u.is_alive) {
-            let dist = ((npc_units[i].x as i16 - player_unit.x as i16).pow(2)
-                + (npc_units[i].y as i16 - player_unit.y as i16).pow(2))
-                as u16;
-            if dist < min_dist {
-                min_dist = dist;
-                closest_target = Some((player_unit.x, player_unit.y));
+        let mut closest_target: Option<(usize, u8, u8)> = None;
+
+        // Find the closest unit or city of any player to the NPC unit
+        for (player_idx, player) in players.iter().enumerate() {
+            for player_unit in player.units.iter().filter(|u| u.is_alive) {
+                let dist = ((npc_units[i].x as i16 - player_unit.x as i16).pow(2)
+                    + (npc_units[i].y as i16 - player_unit.y as i16).pow(2))
+                    as u16;
+                if dist < min_dist {
+                    min_dist = dist;
+                    closest_target = Some((player_idx, player_unit.x, player_unit.y));
+                }
             }
-        }
 
-        for city in player.cities.iter() {
-            let dist = ((npc_units[i].x as i16 - city.x as i16).pow(2)
-                + (npc_units[i].y as i16 - city.y as i16).pow(2)) as u16;
-            if dist < min_dist {
-                min_dist = dist;
-                closest_target = Some((city.x, city.y));
+            for city in player.cities.iter() {
+                let dist = ((npc_units[i].x as i16 - city.x as i16).pow(2)
+                    + (npc_units[i].y as i16 - city.y as i16).pow(2))
+                    as u16;
+                if dist < min_dist {
+                    min_dist = dist;
+                    closest_target = Some((player_idx, city.x, city.y));
+                }
             }
         }
 
         // If a closest target was found, make decisions for NPC units based on the proximity to this target
-        if let Some((target_x, target_y)) = closest_target {
+        if let Some((player_idx, target_x, target_y)) = closest_target {
             let dist_x = (npc_units[i].x as i16 - target_x as i16).abs();
             let dist_y = (npc_units[i].y as i16 - target_y as i16).abs();
             let dist = std::cmp::max(dist_x, dist_y) as u8;
 
-            if dist == 1 {
+            // ranged units shoot from a distance, melee units attack adjacent targets
+            if dist <= npc_units[i].get_attack_range() {
+                let player = &mut *players[player_idx];
                 let is_player_unit = player
                     .units
                     .iter_mut()
@@ -230,11 +287,19 @@ fn process_npc_movements_and_attacks(
                 });
                 if is_city_with_wall && is_player_unit {
                     // attack unit that stay in the city with wall
-                    let player_unit = player
-                        .units
-                        .iter_mut()
-                        .find(|u| u.x == target_x && u.y == target_y && u.is_alive);
-                    npc_units[i].attack_unit(player_unit.unwrap(), Some(true))?;
+                    let defender_idx =
+                        tile_defender_index(&player.units, target_x, target_y).unwrap();
+                    let player_unit = &mut player.units[defender_idx];
+                    let modifiers = CombatModifiers::new(map, &npc_units[i], player_unit, true);
+                    npc_units[i].attack_unit(
+                        player_unit,
+                        modifiers,
+                        randomness.next_below(COMBAT_RANDOM_RANGE) as u8,
+                    )?;
+                    if player_unit.is_captured() {
+                        npc_units.push(player_unit.captured_by(*next_unit_id, *npc_key));
+                        *next_unit_id += 1;
+                    }
                     if !npc_units[i].is_alive {
                         player.resources.gems = player
                             .resources
@@ -242,12 +307,20 @@ fn process_npc_movements_and_attacks(
                             .checked_add(GEMS_PER_KILL[difficulty_level as usize] as u32)
                             .unwrap_or(u32::MAX);
                     }
-                } else if let Some(player_unit) = player
-                    .units
-                    .iter_mut()
-                    .find(|u| u.x == target_x && u.y == target_y && u.is_alive)
+                } else if let Some(defender_idx) =
+                    tile_defender_index(&player.units, target_x, target_y)
                 {
-                    npc_units[i].attack_unit(player_unit, None)?;
+                    let player_unit = &mut player.units[defender_idx];
+                    let modifiers = CombatModifiers::new(map, &npc_units[i], player_unit, false);
+                    npc_units[i].attack_unit(
+                        player_unit,
+                        modifiers,
+                        randomness.next_below(COMBAT_RANDOM_RANGE) as u8,
+                    )?;
+                    if player_unit.is_captured() {
+                        npc_units.push(player_unit.captured_by(*next_unit_id, *npc_key));
+                        *next_unit_id += 1;
+                    }
                     if !npc_units[i].is_alive {
                         player.resources.gems = player
                             .resources
@@ -255,12 +328,32 @@ fn process_npc_movements_and_attacks(
                             .checked_add(GEMS_PER_KILL[difficulty_level as usize] as u32)
                             .unwrap_or(u32::MAX);
                     }
-                } else if let Some(player_city) = player
+                } else if let Some(city_idx) = player
                     .cities
-                    .iter_mut()
-                    .find(|c| c.x == target_x && c.y == target_y && c.health > 0)
+                    .iter()
+                    .position(|c| c.x == target_x && c.y == target_y)
                 {
-                    npc_units[i].attack_city(player_city)?;
+                    npc_units[i].attack_city(
+                        &mut player.cities[city_idx],
+                        randomness.next_below(COMBAT_RANDOM_RANGE) as u8,
+                    )?;
+                    // there are no player units left in the city, barbarian melee units take it over
+                    if player.cities[city_idx].health == 0 && npc_units[i].can_capture_city() {
+                        let city = player.cities.remove(city_idx);
+                        let mut captured_city =
+                            city.captured_by(*next_city_id, *npc_key, randomness);
+                        // barbarians always keep the cities they capture
+                        captured_city.status = CityStatus::Normal;
+                        msg!(
+                            "Barbarians captured city #{}, now city #{}",
+                            city.city_id,
+                            captured_city.city_id
+                        );
+                        npc_units[i].x = captured_city.x;
+                        npc_units[i].y = captured_city.y;
+                        npc_cities.push(captured_city);
+                        *next_city_id += 1;
+                    }
                     if !npc_units[i].is_alive {
                         player.resources.gems = player
                             .resources
@@ -286,7 +379,11 @@ fn process_npc_movements_and_attacks(
 
                 if new_x < MAP_BOUND
                     && new_y < MAP_BOUND
-                    && !is_occupied(new_x, new_y, &player.units, npc_units, &player.cities)
+                    && map[new_y as usize * MAP_BOUND as usize + new_x as usize]
+                        .terrain
+                        .movement_cost()
+                        .is_some()
+                    && !is_occupied(new_x, new_y, &npc_units[i], players, npc_units)
                 {
                     npc_units[i].x = new_x;
                     npc_units[i].y = new_y;
@@ -304,51 +401,233 @@ fn process_npc_movements_and_attacks(
     Ok(())
 }
 
-fn is_occupied(
-    x: u8,
-    y: u8,
-    player_units: &[Unit],
-    npc_units: &[Unit],
-    player_cities: &[City],
-) -> bool {
-    player_units
+fn process_alert_unit_attacks(
+    player: &mut Player,
+    npc_units: &mut [Unit],
+    map: &[Terrain],
+    difficulty_level: u8,
+    randomness: &mut Randomness,
+) -> Result<()> {
+    for i in 0..player.units.len() {
+        let unit = &mut player.units[i];
+        // a unit that moved or attacked during the turn has already used it
+        if !unit.is_alive
+            || unit.stance != UnitStance::Alert
+            || unit.movement_range < unit.get_movement_range()
+        {
+            continue;
+        }
+        // attack the weakest barbarian unit that ended its turn next to the unit
+        if let Some(target) = npc_units
+            .iter_mut()
+            .filter(|u| {
+                u.is_alive
+                    && !u.is_civilian()
+                    && chebyshev_distance(unit.x, unit.y, u.x, u.y) == MELEE_ATTACK_RANGE
+            })
+            .min_by_key(|u| u.health)
+        {
+            let modifiers = CombatModifiers::new(map, unit, target, false);
+            unit.attack_unit(
+                target,
+                modifiers,
+                randomness.next_below(COMBAT_RANDOM_RANGE) as u8,
+            )?;
+            // the attack takes the turn of the unit, so it doesn't heal
+            unit.movement_range = 0;
+            msg!(
+                "Unit #{} on alert attacked unit #{}",
+                unit.unit_id,
+                target.unit_id
+            );
+            if !target.is_alive {
+                player.resources.gems = player
+                    .resources
+                    .gems
+                    .saturating_add(GEMS_PER_KILL[difficulty_level as usize] as u32);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn process_npc_city_strikes(
+    npc_cities: &[City],
+    players: &mut [&mut Player],
+    randomness: &mut Randomness,
+) {
+    for city in npc_cities.iter().filter(|c| c.wall_health > 0) {
+        // bombard the weakest unit of any player within range
+        // a military unit covers the civilian stacked with it
+        if let Some((player_idx, defender_idx)) = players
+            .iter()
+            .enumerate()
+            .flat_map(|(player_idx, player)| player.units.iter().map(move |u| (player_idx, u)))
+            .filter(|(_, u)| {
+                u.is_alive && chebyshev_distance(city.x, city.y, u.x, u.y) <= CITY_STRIKE_RANGE
+            })
+            .min_by_key(|(_, u)| u.health)
+            .and_then(|(player_idx, u)| {
+                tile_defender_index(&players[player_idx].units, u.x, u.y)
+                    .map(|defender_idx| (player_idx, defender_idx))
+            })
+        {
+            let target = &mut players[player_idx].units[defender_idx];
+            let damage =
+                city.strike_damage(target, randomness.next_below(COMBAT_RANDOM_RANGE) as u8);
+            target.apply_damage(damage);
+            msg!(
+                "Barbarian city #{} struck unit #{} for {} damage",
+                city.city_id,
+                target.unit_id,
+                damage
+            );
+        }
+    }
+}
+
+/// Wakes up the sleeping units that have a barbarian unit or a unit of a civilization at war next to them.
+fn wake_up_sleeping_units(game: &Game, players: &mut [&mut Player], npc_units: &[Unit]) {
+    for i in 0..players.len() {
+        let player_key = players[i].player;
+        let enemy_tiles: Vec<(u8, u8)> = npc_units
+            .iter()
+            .chain(
+                players
+                    .iter()
+                    .filter(|p| game.is_at_war(&player_key, &p.player))
+                    .flat_map(|p| p.units.iter()),
+            )
+            .filter(|u| u.is_alive)
+            .map(|u| (u.x, u.y))
+            .collect();
+        for unit in players[i]
+            .units
+            .iter_mut()
+            .filter(|u| u.is_alive && u.stance == UnitStance::Sleep)
+        {
+            if enemy_tiles
+                .iter()
+                .any(|&(x, y)| chebyshev_distance(unit.x, unit.y, x, y) == 1)
+            {
+                unit.clear_stance();
+                msg!("Unit #{} woke up", unit.unit_id);
+            }
+        }
+    }
+}
+
+/// Checks whether the NPC `unit` can't enter the tile, because of a unit or a city of any player
+/// or a barbarian unit that can't share the tile with it.
+fn is_occupied(x: u8, y: u8, unit: &Unit, players: &[&mut Player], npc_units: &[Unit]) -> bool {
+    players.iter().any(|player| {
+        player
+            .units
+            .iter()
+            .any(|u| u.x == x && u.y == y && u.is_alive)
+            || player.cities.iter().any(|c| c.x == x && c.y == y)
+    }) || npc_units
         .iter()
-        .any(|u| u.x == x && u.y == y && u.is_alive)
-        || npc_units.iter().any(|u| u.x == x && u.y == y && u.is_alive)
-        || player_cities.iter().any(|c| c.x == x && c.y == y)
+        .any(|u| u.x == x && u.y == y && u.is_alive && !unit.can_share_tile(u))
 }
 
 fn required_food_for_growth(population: u32) -> u32 {
     (0.1082 * (population as f64).powf(2.0) + 10.171 * population as f64 + 1.929) as u32
 }
 
-pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
+pub fn end_turn<'info>(ctx: Context<'_, '_, '_, 'info, EndTurn<'info>>) -> Result<()> {
     // check if the game is over via defeat or victory
     if ctx.accounts.game.defeat || ctx.accounts.game.victory {
         return Ok(());
     }
 
+    // players end their turns one after another in the order they joined the game
+    if !ctx
+        .accounts
+        .game
+        .is_current_player(&ctx.accounts.player_account.player)
+    {
+        return err!(GameError::NotYourTurn);
+    }
+
+    // the turn is committed first and ended in a later slot,
+    // a combat action that is still pending has to be resolved before
+    let turn_bytes = ctx.accounts.game.turn.to_le_bytes();
+    if !reveal_randomness(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[b"end_turn", &turn_bytes],
+        &ctx.accounts.recent_slothashes,
+    )? {
+        return Ok(());
+    }
+
     // Calculate and update player's resources
-    let (gold, wood, stone, iron, horses, science) =
+    let (gold, wood, stone, iron, horses, food, science) =
         calculate_resources(&ctx.accounts.player_account);
     ctx.accounts
         .player_account
-        .update_resources(gold, wood, stone, iron, horses)?;
+        .update_resources(gold, wood, stone, iron, horses, food)?;
 
-    let player_account = &mut ctx.accounts.player_account;
+    // barbarians act once per round, after the last player in the turn order,
+    // against the units and cities of every player
+    if ctx.accounts.game.is_last_in_round() {
+        if ctx.remaining_accounts.iter().any(|info| !info.is_writable) {
+            return err!(GameError::InvalidPlayerAccounts);
+        }
+        let mut other_accounts = load_other_players(
+            &ctx.accounts.game,
+            &ctx.accounts.player_account.player,
+            ctx.remaining_accounts,
+        )?;
+        let mut players: Vec<&mut Player> = std::iter::once(&mut *ctx.accounts.player_account)
+            .chain(other_accounts.iter_mut().map(|account| &mut **account))
+            .collect();
 
-    process_npc_movements_and_attacks(
-        &mut ctx.accounts.npc_account.units,
+        let game: &mut Game = &mut ctx.accounts.game;
+        process_npc_movements_and_attacks(
+            &mut ctx.accounts.npc_account,
+            &mut players,
+            &game.map,
+            game.difficulty_level,
+            &mut game.randomness,
+        )?;
+        process_npc_city_strikes(
+            &ctx.accounts.npc_account.cities,
+            &mut players,
+            &mut game.randomness,
+        );
+        // Auto-healing of barbarian cities, including the ones left at 0 HP by ranged or siege units
+        for city in ctx.accounts.npc_account.cities.iter_mut() {
+            if city.health < 100 {
+                city.health = std::cmp::min(city.health + 5, 100);
+            }
+        }
+        for player in players.iter_mut() {
+            player.units.retain(|u| u.is_alive);
+        }
+        wake_up_sleeping_units(game, &mut players, &ctx.accounts.npc_account.units);
+
+        for account in other_accounts.iter() {
+            account.exit(ctx.program_id)?;
+        }
+    }
+
+    let player_account = &mut ctx.accounts.player_account;
+    let game: &mut Game = &mut ctx.accounts.game;
+    process_alert_unit_attacks(
         player_account,
-        ctx.accounts.game.difficulty_level,
+        &mut ctx.accounts.npc_account.units,
+        &game.map,
+        game.difficulty_level,
+        &mut game.randomness,
     )?;
 
     // Retain only alive units in the game
     player_account.units.retain(|u| u.is_alive);
-    player_account.cities.retain(|c| c.health > 0);
     ctx.accounts.npc_account.units.retain(|u| u.is_alive);
-    ctx.accounts.npc_account.cities.retain(|c| c.health > 0);
 
+    let mut food_stockpile = player_account.resources.food;
     for i in 0..player_account.cities.len() {
         let all_controlled_tiles: Vec<TileCoordinate> = player_account
             .cities
@@ -359,11 +638,23 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
 
         let city = &mut player_account.cities[i];
 
-        city.accumulated_food += city.food_yield as i32;
+        // city being razed loses a citizen every turn instead of growing
+        if city.status == CityStatus::Razing {
+            city.population = city.population.saturating_sub(1);
+            msg!(
+                "City #{} is being razed, {} citizens left",
+                city.city_id,
+                city.population
+            );
+            continue;
+        }
 
-        // Deduct food for population maintenance
-        let food_consumption = city.population * 2; // 2 food per citizen
-        city.accumulated_food -= food_consumption as i32;
+        // Add the food left after population maintenance,
+        // the surplus of a city that cannot grow has already been sent to the stockpile
+        let food_surplus = city.food_surplus();
+        if food_surplus < 0 || city.population < city.housing {
+            city.accumulated_food += food_surplus;
+        }
 
         if city.accumulated_food >= 0 {
             let required_food = required_food_for_growth(city.population);
@@ -371,6 +662,10 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
                 city.population += 1;
                 city.accumulated_food = 0;
             }
+        } else if food_stockpile >= city.accumulated_food.unsigned_abs() {
+            // Famine relief from the player's food stockpile
+            food_stockpile -= city.accumulated_food.unsigned_abs();
+            city.accumulated_food = 0;
         } else {
             // Handle population decrease due to food shortage
             if city.population > 1 {
@@ -379,6 +674,9 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
             }
         }
 
+        // city can strike again next turn
+        city.has_struck = false;
+
         // Auto-healing of cities
         if city.health < 100 {
             city.health = std::cmp::min(city.health + 5, 100);
@@ -395,12 +693,28 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
             let adjacent_tiles = find_adjacent_tiles(&city.controlled_tiles, &all_controlled_tiles);
 
             if !adjacent_tiles.is_empty() {
-                let clock = Clock::get()?;
-                let random_factor = clock.unix_timestamp as usize % adjacent_tiles.len();
-                city.controlled_tiles.push(adjacent_tiles[random_factor]);
+                let random_factor = ctx
+                    .accounts
+                    .game
+                    .randomness
+                    .next_below(adjacent_tiles.len() as u64);
+                city.controlled_tiles
+                    .push(adjacent_tiles[random_factor as usize]);
             };
         }
     }
+    player_account.resources.food = food_stockpile;
+
+    // razed cities are removed and rewarded with gems
+    let razed_cities = player_account
+        .cities
+        .iter()
+        .filter(|c| c.status == CityStatus::Razing && c.population == 0)
+        .count() as u32;
+    player_account.cities.retain(|c| c.population > 0);
+    player_account.resources.gems = player_account.resources.gems.saturating_add(
+        razed_cities * GEMS_PER_CITY_DESTROYED[ctx.accounts.game.difficulty_level as usize] as u32,
+    );
 
     // The healing should happen only after NPC attacks
     // Reset units' movement range & heal if needed
@@ -413,25 +727,54 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
     // Check research progress
     ctx.accounts.player_account.add_research_points(science)?;
 
-    let spawn_interval = SPAWN_INTERVAL[ctx.accounts.game.difficulty_level as usize];
+    // Barbarians spawn and the game turn counter moves only once every player has ended their turn
+    let round_completed = ctx.accounts.game.advance_turn(Clock::get()?.unix_timestamp);
+    if round_completed {
+        spawn_npc_units(
+            &mut ctx.accounts.game,
+            game_key,
+            &mut ctx.accounts.npc_account,
+        )?;
+        ctx.accounts.game.turn += 1;
+    }
+
+    // if player has no units and no cities, the player is eliminated from the turn order
+    // the game is lost once there are no players left
+    if ctx.accounts.player_account.units.is_empty() && ctx.accounts.player_account.cities.is_empty()
+    {
+        let player_key = ctx.accounts.player_account.player;
+        ctx.accounts.game.remove_player(&player_key);
+        if ctx.accounts.game.players.is_empty() {
+            ctx.accounts.game.defeat = true;
+        }
+    } else if ctx.accounts.npc_account.units.is_empty()
+        && ctx.accounts.npc_account.cities.is_empty()
+    {
+        ctx.accounts.game.victory = true;
+    }
+
+    Ok(())
+}
+
+fn spawn_npc_units(game: &mut Game, game_key: Pubkey, npc_account: &mut Npc) -> Result<()> {
+    let spawn_interval = SPAWN_INTERVAL[game.difficulty_level as usize];
 
     // Determine the epoch based on the game turn and difficulty level
-    let epoch = match ctx.accounts.game.difficulty_level {
-        0 => ctx.accounts.game.turn / 100, // Easy: NPCs evolve every 100 turns
-        1 => ctx.accounts.game.turn / 80,  // Medium: NPCs evolve every 80 turns
-        2 => ctx.accounts.game.turn / 60,  // Hard: NPCs evolve every 60 turns
-        _ => ctx.accounts.game.turn / 80,  // Default to Medium
+    let epoch = match game.difficulty_level {
+        0 => game.turn / 100, // Easy: NPCs evolve every 100 turns
+        1 => game.turn / 80,  // Medium: NPCs evolve every 80 turns
+        2 => game.turn / 60,  // Hard: NPCs evolve every 60 turns
+        _ => game.turn / 80,  // Default to Medium
     };
 
-    if ctx.accounts.game.turn % spawn_interval as u32 == 0 {
-        let clock = Clock::get()?;
-        let random_factor = clock.unix_timestamp % 10;
-
+    if game.turn % spawn_interval as u32 == 0 {
         // Temporary vector to store new units.
         let mut new_units = Vec::new();
-        let mut next_npc_id = ctx.accounts.npc_account.next_unit_id;
+        let mut next_npc_id = npc_account.next_unit_id;
 
-        for city in &ctx.accounts.npc_account.cities {
+        for city in &npc_account.cities {
+            // every barbarian city rolls its own unit type
+            let random_factor = game.randomness.next_below(10);
             let unit_type = match epoch {
                 0 => {
                     if random_factor < 5 {
@@ -465,8 +808,8 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
 
             let new_unit = Unit::new(
                 next_npc_id,
-                ctx.accounts.npc_account.player,
-                ctx.accounts.game.key(),
+                npc_account.player,
+                game_key,
                 unit_type,
                 city.x,
                 city.y,
@@ -474,35 +817,68 @@ pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
             new_units.push(new_unit);
             next_npc_id += 1;
         }
-        ctx.accounts.npc_account.units.append(&mut new_units);
-        ctx.accounts.npc_account.next_unit_id = next_npc_id;
+        npc_account.units.append(&mut new_units);
+        npc_account.next_unit_id = next_npc_id;
     }
-    // if player has no units and no cities set game defeat to true
-    if ctx.accounts.player_account.units.is_empty() && ctx.accounts.player_account.cities.is_empty()
-    {
-        ctx.accounts.game.defeat = true;
-    } else if ctx.accounts.npc_account.units.is_empty()
-        && ctx.accounts.npc_account.cities.is_empty()
-    {
-        ctx.accounts.game.victory = true;
+    Ok(())
+}
+
+/// Passes the turn of a player who didn't end it within `TURN_TIMEOUT` to the next player.
+/// Nothing of the skipped turn is processed, and barbarians don't act in a round that ends with it.
+pub fn skip_turn(ctx: Context<SkipTurn>) -> Result<()> {
+    let game = &mut ctx.accounts.game;
+    if game.defeat || game.victory {
+        return err!(GameError::GameOver);
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if now < game.turn_started_at.saturating_add(TURN_TIMEOUT) {
+        return err!(GameError::TurnNotTimedOut);
+    }
+
+    // an action committed by the skipped player can't be resolved anymore
+    let skipped_account = &mut ctx.accounts.skipped_account;
+    skipped_account.pending_commitment = None;
+    msg!("Turn of {} skipped", skipped_account.player);
+
+    if game.advance_turn(now) {
+        game.turn += 1;
     }
 
-    ctx.accounts.game.turn += 1;
     Ok(())
 }
 
-pub fn close_game(_ctx: Context<Close>) -> Result<()> {
+pub fn close_game(ctx: Context<Close>) -> Result<()> {
+    // other players would be left with accounts that point to a closed game,
+    // the host may have been eliminated from the turn order already
+    let game = &ctx.accounts.game;
+    if game.players.iter().any(|p| *p != game.player) {
+        return err!(GameError::PlayersStillJoined);
+    }
+
+    let game_key = ctx.accounts.game.key();
+    ctx.accounts.registry.remove_game(&game_key);
+
     Ok(())
 }
 
 #[derive(Accounts)]
 pub struct InitializeGame<'info> {
+    #[account(
+        init_if_needed,
+        seeds=[b"REGISTRY", player.key().as_ref()],
+        bump,
+        payer = player,
+        space = std::mem::size_of::<GameRegistry>() + 32 * MAX_ACTIVE_GAMES as usize + 8
+    )]
+    pub registry: Box<Account<'info, GameRegistry>>,
     #[account(
         init,
-        seeds=[b"GAME", player.key().as_ref()],
+        seeds=[b"GAME", player.key().as_ref(), registry.next_game_id.to_le_bytes().as_ref()],
         bump,
         payer = player,
-        space = std::mem::size_of::<Game>() + 8
+        space = std::mem::size_of::<Game>() +
+            32 * MAX_PLAYERS as usize +
+            std::mem::size_of::<Relation>() * MAX_RELATIONS as usize + 8
     )]
     pub game: Box<Account<'info, Game>>,
     #[account(mut)]
@@ -512,12 +888,41 @@ pub struct InitializeGame<'info> {
 
 #[derive(Accounts)]
 pub struct EndTurn<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Box<Account<'info, Game>>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = game,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
     pub player_account: Account<'info, Player>,
-    #[account(mut, has_one = player)]
+    #[account(mut, has_one = game)]
     pub npc_account: Account<'info, Npc>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SkipTurn<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        has_one = player,
+        has_one = game,
+        constraint = game.has_player(&player_account.player) @ GameError::NotInGame
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&skipped_account.player) @ GameError::InvalidPlayerAccounts,
+        constraint = skipped_account.key() != player_account.key() @ GameError::InvalidPlayerAccounts
+    )]
+    pub skipped_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
@@ -530,6 +935,8 @@ pub struct Close<'info> {
     player_account: Account<'info, Player>,
     #[account(mut, close = player, has_one = player)]
     npc_account: Account<'info, Npc>,
+    #[account(mut, has_one = player)]
+    registry: Box<Account<'info, GameRegistry>>,
     #[account(mut)]
     player: Signer<'info>,
 }
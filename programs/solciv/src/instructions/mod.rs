@@ -1,15 +1,19 @@
 pub mod city;
+pub mod diplomacy;
 pub mod game;
 pub mod mint;
 pub mod npc;
 pub mod player;
 pub mod science;
+pub mod session;
 pub mod unit;
 
 pub use city::*;
+pub use diplomacy::*;
 pub use game::*;
 pub use mint::*;
 pub use npc::*;
 pub use player::*;
 pub use science::*;
+pub use session::*;
 pub use unit::*;
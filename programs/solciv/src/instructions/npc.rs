@@ -60,6 +60,7 @@ pub fn initialize_npc(
 
 #[derive(Accounts)]
 pub struct InitializeNpc<'info> {
+    #[account(has_one = player)]
     pub game: Box<Account<'info, Game>>,
 
     #[account(
@@ -1,52 +1,134 @@
 use crate::consts::*;
+use crate::errors::*;
 use crate::state::*;
 use anchor_lang::prelude::*;
 
 pub fn initialize_player(ctx: Context<InitializePlayer>, position: TileCoordinate) -> Result<()> {
-    ctx.accounts.player_account.game = ctx.accounts.game.key();
-    ctx.accounts.player_account.player = ctx.accounts.player.key();
-    ctx.accounts.player_account.points = 0;
-    ctx.accounts.player_account.next_city_id = 0;
-    ctx.accounts.player_account.next_unit_id = 0;
+    let game_key = ctx.accounts.game.key();
+    let player_key = ctx.accounts.player.key();
+
+    add_player_to_game(&mut ctx.accounts.game, player_key)?;
+    setup_player_account(
+        &mut ctx.accounts.player_account,
+        game_key,
+        player_key,
+        position,
+    );
+
+    msg!("Player created!");
+
+    Ok(())
+}
+
+pub fn join_game(ctx: Context<JoinGame>, position: TileCoordinate) -> Result<()> {
+    let game_key = ctx.accounts.game.key();
+    let player_key = ctx.accounts.player.key();
+
+    add_player_to_game(&mut ctx.accounts.game, player_key)?;
+    setup_player_account(
+        &mut ctx.accounts.player_account,
+        game_key,
+        player_key,
+        position,
+    );
+
+    ctx.accounts.registry.player = player_key;
+    ctx.accounts.registry.add_game(game_key)?;
+
+    msg!("Player joined the game!");
+
+    Ok(())
+}
+
+pub fn leave_game(ctx: Context<LeaveGame>) -> Result<()> {
+    let player_key = ctx.accounts.player.key();
+
+    if ctx.accounts.game.player == player_key {
+        return err!(GameError::HostCannotLeave);
+    }
+
+    // the next player gets the whole time for the turn the leaving player had
+    if ctx.accounts.game.is_current_player(&player_key) {
+        ctx.accounts.game.turn_started_at = Clock::get()?.unix_timestamp;
+    }
+    ctx.accounts.game.remove_player(&player_key);
+
+    let game_key = ctx.accounts.game.key();
+    ctx.accounts.registry.remove_game(&game_key);
+
+    msg!("Player left the game!");
+
+    Ok(())
+}
+
+fn add_player_to_game(game: &mut Game, player: Pubkey) -> Result<()> {
+    if game.defeat || game.victory {
+        return err!(GameError::GameOver);
+    }
+    if game.has_player(&player) {
+        return err!(GameError::AlreadyJoined);
+    }
+    if game.players.len() >= MAX_PLAYERS as usize {
+        return err!(GameError::GameFull);
+    }
+
+    game.players.push(player);
+
+    Ok(())
+}
+
+fn setup_player_account(
+    player_account: &mut Player,
+    game_key: Pubkey,
+    player_key: Pubkey,
+    position: TileCoordinate,
+) {
+    player_account.game = game_key;
+    player_account.player = player_key;
+    player_account.points = 0;
+    player_account.next_city_id = 0;
+    player_account.next_unit_id = 0;
     // @todo: consider implementing helper methods for initializing the resources, units or other default things
-    ctx.accounts.player_account.resources = Resources {
+    player_account.resources = Resources {
         gold: 0,
         wood: 0,
         stone: 0,
         iron: 0,
         gems: 0,
         horses: 0,
+        food: 0,
     };
     // player starts with 3 units: Settler, Builder, Warrior
-    ctx.accounts.player_account.units = vec![
+    player_account.units = vec![
         Unit::new(
             0,
-            ctx.accounts.player.key(),
-            ctx.accounts.game.key(),
+            player_key,
+            game_key,
             UnitType::Settler,
             position.x,
             position.y,
         ),
         Unit::new(
             1,
-            ctx.accounts.player.key(),
-            ctx.accounts.game.key(),
+            player_key,
+            game_key,
             UnitType::Builder,
             position.x + 1,
             position.y,
         ),
         Unit::new(
             2,
-            ctx.accounts.player.key(),
-            ctx.accounts.game.key(),
+            player_key,
+            game_key,
             UnitType::Warrior,
             position.x,
             position.y + 1,
         ),
     ];
-    ctx.accounts.player_account.next_unit_id = 3;
+    player_account.next_unit_id = 3;
 
-    ctx.accounts.player_account.researched_technologies = vec![];
+    player_account.researched_technologies = vec![];
+    player_account.discovered_tiles = [0; 50];
 
     /* Set surrounding tiles to 'discovered' */
     let start_x = position.x.saturating_sub(2).max(0);
@@ -56,18 +138,42 @@ pub fn initialize_player(ctx: Context<InitializePlayer>, position: TileCoordinat
 
     for i in start_x..=end_x {
         for j in start_y..=end_y {
-            let index = (j as usize) * MAP_BOUND as usize + i as usize;
-            ctx.accounts.game.map[index].discovered = true;
+            player_account.discover_tile(i, j);
         }
     }
+}
 
-    msg!("Player created!");
+#[derive(Accounts)]
+pub struct InitializePlayer<'info> {
+    #[account(mut, has_one = player)]
+    pub game: Box<Account<'info, Game>>,
 
-    Ok(())
+    #[account(
+        init,
+        seeds=[
+            b"PLAYER",
+            game.key().as_ref(),
+            player.key().as_ref()
+        ],
+        bump,
+        payer = player,
+        space = std::mem::size_of::<Player>() +
+            4 + (15 * MAX_CITIES as usize) +
+            std::mem::size_of::<Unit>() * MAX_UNITS as usize +
+            std::mem::size_of::<City>() * MAX_CITIES as usize +
+            std::mem::size_of::<Tile>() * MAX_UPGRADED_TILES as usize +
+            std::mem::size_of::<BuildingType>() * MAX_BUILDINGS as usize +
+            std::mem::size_of::<Resources>() + 8)
+    ]
+    pub player_account: Box<Account<'info, Player>>,
+
+    #[account(mut)]
+    pub player: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct InitializePlayer<'info> {
+pub struct JoinGame<'info> {
     #[account(mut)]
     pub game: Box<Account<'info, Game>>,
 
@@ -90,7 +196,28 @@ pub struct InitializePlayer<'info> {
     ]
     pub player_account: Box<Account<'info, Player>>,
 
+    #[account(
+        init_if_needed,
+        seeds=[b"REGISTRY", player.key().as_ref()],
+        bump,
+        payer = player,
+        space = std::mem::size_of::<GameRegistry>() + 32 * MAX_ACTIVE_GAMES as usize + 8
+    )]
+    pub registry: Box<Account<'info, GameRegistry>>,
+
     #[account(mut)]
     pub player: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
+
+#[derive(Accounts)]
+pub struct LeaveGame<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(mut, close = player, has_one = player, has_one = game)]
+    pub player_account: Box<Account<'info, Player>>,
+    #[account(mut, has_one = player)]
+    pub registry: Box<Account<'info, GameRegistry>>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
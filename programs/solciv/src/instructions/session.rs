@@ -0,0 +1,57 @@
+use crate::consts::*;
+use crate::errors::*;
+use crate::state::*;
+use anchor_lang::prelude::*;
+
+pub fn create_session(ctx: Context<CreateSession>, session_key: Pubkey, turns: u32) -> Result<()> {
+    if turns == 0 || turns > MAX_SESSION_TURNS {
+        return err!(SessionError::InvalidDuration);
+    }
+
+    let session_token = &mut ctx.accounts.session_token;
+    session_token.player = ctx.accounts.player.key();
+    session_token.game = ctx.accounts.game.key();
+    session_token.session_key = session_key;
+    session_token.expires_at_turn = ctx.accounts.game.turn + turns;
+
+    msg!("Session created!");
+
+    Ok(())
+}
+
+pub fn revoke_session(_ctx: Context<RevokeSession>) -> Result<()> {
+    msg!("Session revoked!");
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(session_key: Pubkey)]
+pub struct CreateSession<'info> {
+    pub game: Box<Account<'info, Game>>,
+    #[account(has_one = player, has_one = game)]
+    pub player_account: Account<'info, Player>,
+    #[account(
+        init,
+        seeds=[
+            b"SESSION",
+            player_account.key().as_ref(),
+            session_key.as_ref()
+        ],
+        bump,
+        payer = player,
+        space = std::mem::size_of::<SessionToken>() + 8
+    )]
+    pub session_token: Account<'info, SessionToken>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    #[account(mut, close = player, has_one = player)]
+    pub session_token: Account<'info, SessionToken>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
@@ -3,8 +3,14 @@ use crate::errors::*;
 use crate::state::*;
 use crate::utils::*;
 use anchor_lang::prelude::*;
-
-pub fn move_unit(ctx: Context<MoveUnit>, unit_id: u32, x: u8, y: u8) -> Result<()> {
+use anchor_lang::solana_program::sysvar::slot_hashes;
+
+pub fn move_unit<'info>(
+    ctx: Context<'_, '_, '_, 'info, MoveUnit<'info>>,
+    unit_id: u32,
+    x: u8,
+    y: u8,
+) -> Result<()> {
     let unit = ctx
         .accounts
         .player_account
@@ -24,26 +30,66 @@ pub fn move_unit(ctx: Context<MoveUnit>, unit_id: u32, x: u8, y: u8) -> Result<(
         return err!(UnitError::CannotMove);
     }
 
-    // Check if the new position is within the movement_range
-    // Manhattan Distance:
-    let dist = ((unit.x as i16 - x as i16).abs() + (unit.y as i16 - y as i16).abs()) as u8;
-    msg!("Initial position: ({}, {})", unit.x, unit.y);
-    msg!("New position: ({}, {})", x, y);
-    msg!("Distance: {}", dist);
-    if dist > unit.movement_range {
-        return err!(UnitError::OutOfMovementRange);
+    let map_idx = (y as usize) * MAP_BOUND as usize + x as usize;
+    if ctx.accounts.game.map[map_idx]
+        .terrain
+        .movement_cost()
+        .is_none()
+    {
+        return err!(UnitError::ImpassableTerrain);
     }
 
-    // Check if the tile is not occupied by another unit
+    // Check if the tile is not occupied by another unit, only a civilian and a military unit can share a tile
     if ctx
         .accounts
         .player_account
         .units
         .iter()
-        .any(|u| u.x == x && u.y == y && u.unit_id != unit_id)
+        .any(|u| u.x == x && u.y == y && u.unit_id != unit_id && !unit.can_share_tile(u))
+    {
+        return err!(UnitError::TileOccupied);
+    }
+
+    // A unit can't stop on a unit or a city of another civilization,
+    // barbarian units and cities, and the ones of civilizations at war with the player also block the passage
+    let player_key = ctx.accounts.player_account.player;
+    let mut other_players =
+        load_other_players(&ctx.accounts.game, &player_key, ctx.remaining_accounts)?;
+    let game = &ctx.accounts.game;
+    let npc_account = &ctx.accounts.npc_account;
+    let is_taken = |units: &[Unit], cities: &[City], tile_x: u8, tile_y: u8| {
+        units
+            .iter()
+            .any(|u| u.x == tile_x && u.y == tile_y && u.is_alive)
+            || cities.iter().any(|c| c.x == tile_x && c.y == tile_y)
+    };
+    if is_taken(&npc_account.units, &npc_account.cities, x, y)
+        || other_players
+            .iter()
+            .any(|p| is_taken(&p.units, &p.cities, x, y))
     {
         return err!(UnitError::TileOccupied);
     }
+    let is_blocked = |tile_x: u8, tile_y: u8| {
+        is_taken(&npc_account.units, &npc_account.cities, tile_x, tile_y)
+            || other_players
+                .iter()
+                .filter(|p| game.is_at_war(&player_key, &p.player))
+                .any(|p| is_taken(&p.units, &p.cities, tile_x, tile_y))
+    };
+
+    // Find the cheapest path to the new position within the movement_range
+    let dist = find_path_cost(
+        &ctx.accounts.game.map,
+        (unit.x, unit.y),
+        (x, y),
+        unit.movement_range,
+        is_blocked,
+    )
+    .ok_or(UnitError::OutOfMovementRange)?;
+    msg!("Initial position: ({}, {})", unit.x, unit.y);
+    msg!("New position: ({}, {})", x, y);
+    msg!("Movement cost: {}", dist);
 
     let units = &mut ctx.accounts.player_account.units;
 
@@ -57,6 +103,8 @@ pub fn move_unit(ctx: Context<MoveUnit>, unit_id: u32, x: u8, y: u8) -> Result<(
     ctx.accounts.player_account.units[unit_idx].x = x;
     ctx.accounts.player_account.units[unit_idx].y = y;
     ctx.accounts.player_account.units[unit_idx].movement_range -= dist;
+    // moving unit leaves its stance
+    ctx.accounts.player_account.units[unit_idx].clear_stance();
 
     // Mark tiles within movement range as discovered
     let start_x = x.saturating_sub(base_movement_range);
@@ -64,20 +112,198 @@ pub fn move_unit(ctx: Context<MoveUnit>, unit_id: u32, x: u8, y: u8) -> Result<(
     let start_y = y.saturating_sub(base_movement_range);
     let end_y = std::cmp::min(y + base_movement_range, 19);
 
+    let mut discovered_tiles = Vec::new();
     for j in start_y..=end_y {
         for i in start_x..=end_x {
             let dist = ((i as i16 - x as i16).abs() + (j as i16 - y as i16).abs()) as u8;
             if dist <= base_movement_range {
-                let index = (j as usize) * 20 + (i as usize);
-                ctx.accounts.game.map[index].discovered = true;
+                discovered_tiles.push(TileCoordinate { x: i, y: j });
             }
         }
     }
+    discover_tiles(
+        ctx.program_id,
+        &ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &mut other_players,
+        &discovered_tiles,
+    )?;
 
     Ok(())
 }
 
-pub fn upgrade_unit(ctx: Context<UpgradeUnit>, unit_id: u32) -> Result<()> {
+pub fn set_stance(ctx: Context<SetStance>, unit_id: u32, stance: UnitStance) -> Result<()> {
+    let unit = ctx
+        .accounts
+        .player_account
+        .units
+        .iter_mut()
+        .find(|u| u.unit_id == unit_id)
+        .ok_or(UnitError::UnitNotFound)?;
+
+    unit.set_stance(stance)?;
+
+    Ok(())
+}
+
+pub fn upgrade_unit_type(ctx: Context<UpgradeUnitType>, unit_id: u32) -> Result<()> {
+    let player_account = &mut ctx.accounts.player_account;
+    let unit_idx = player_account
+        .units
+        .iter()
+        .position(|u| u.unit_id == unit_id)
+        .ok_or(UnitError::UnitNotFound)?;
+    let unit = player_account.units[unit_idx];
+
+    let successor = unit
+        .unit_type
+        .successor()
+        .ok_or(UnitError::NoUpgradeAvailable)?;
+    if !successor.can_recruit(&player_account.researched_technologies) {
+        return err!(CityError::TechnologyNotResearched);
+    }
+    if unit.movement_range == 0 {
+        return err!(UnitError::NoMovementPoints);
+    }
+    if !player_account
+        .cities
+        .iter()
+        .any(|c| c.controls_tile(unit.x, unit.y))
+    {
+        return err!(UnitError::NotInOwnTerritory);
+    }
+
+    // upgrade costs a part of the gold cost and the full resource cost of the new unit type
+    let gold_cost = (Unit::get_gold_cost(successor) * UNIT_UPGRADE_COST_PERCENT / 100) as i32;
+    if player_account.resources.gold < gold_cost {
+        return err!(CityError::InsufficientGold);
+    }
+    let resource_cost = Unit::get_resource_cost(successor);
+    let resource = match successor {
+        UnitType::Swordsman | UnitType::Pikeman => Some(&mut player_account.resources.iron),
+        UnitType::Horseman => Some(&mut player_account.resources.horses),
+        _ => None,
+    };
+    if let Some(resource) = resource {
+        if *resource < resource_cost {
+            return err!(CityError::InsufficientResources);
+        }
+        *resource -= resource_cost;
+    }
+    player_account.resources.gold -= gold_cost;
+
+    player_account.units[unit_idx].upgrade_type(successor);
+
+    msg!("Unit #{} upgraded", unit_id);
+
+    Ok(())
+}
+
+pub fn disband_unit(ctx: Context<DisbandUnit>, unit_id: u32) -> Result<()> {
+    let player_account = &mut ctx.accounts.player_account;
+    let unit_idx = player_account
+        .units
+        .iter()
+        .position(|u| u.unit_id == unit_id)
+        .ok_or(UnitError::UnitNotFound)?;
+    let unit = player_account.units.remove(unit_idx);
+
+    // unit disbanded inside a city helps with the current production
+    if let Some(city) = player_account
+        .cities
+        .iter_mut()
+        .find(|c| c.x == unit.x && c.y == unit.y)
+    {
+        let production = unit.base_production_cost * DISBAND_REFUND_PERCENT / 100;
+        city.accumulated_production += production;
+        msg!(
+            "Unit #{} disbanded in city #{}, +{} production",
+            unit_id,
+            city.city_id,
+            production
+        );
+        return Ok(());
+    }
+
+    // otherwise a part of the iron or horses spent on the unit is returned
+    let refund = unit.disband_refund();
+    let resources = &mut player_account.resources;
+    match unit.unit_type {
+        UnitType::Swordsman | UnitType::Pikeman => {
+            resources.iron = resources.iron.saturating_add(refund)
+        }
+        UnitType::Horseman => resources.horses = resources.horses.saturating_add(refund),
+        _ => {}
+    }
+    msg!("Unit #{} disbanded, +{} resources", unit_id, refund);
+
+    Ok(())
+}
+
+pub fn heal_unit(ctx: Context<HealUnit>, unit_id: u32) -> Result<()> {
+    let player_account = &mut ctx.accounts.player_account;
+    let unit_idx = player_account
+        .units
+        .iter()
+        .position(|u| u.unit_id == unit_id)
+        .ok_or(UnitError::UnitNotFound)?;
+    let unit = player_account.units[unit_idx];
+
+    if unit.health >= 100 {
+        return err!(UnitError::UnitNotDamaged);
+    }
+    if unit.movement_range == 0 {
+        return err!(UnitError::NoMovementPoints);
+    }
+
+    // units heal faster in a city with Barracks and inside own territory
+    let heal_amount =
+        if player_account.cities.iter().any(|c| {
+            c.x == unit.x && c.y == unit.y && c.buildings.contains(&BuildingType::Barracks)
+        }) {
+            HEAL_AMOUNT_BARRACKS
+        } else if player_account
+            .cities
+            .iter()
+            .any(|c| c.controls_tile(unit.x, unit.y))
+        {
+            HEAL_AMOUNT_TERRITORY
+        } else {
+            HEAL_AMOUNT_FIELD
+        };
+
+    // food is taken from the player's stockpile, or supplied by the nearest city
+    // whose territory the unit stands in or next to
+    if player_account.resources.food >= HEAL_FOOD_COST {
+        player_account.resources.food -= HEAL_FOOD_COST;
+    } else {
+        let city = player_account
+            .cities
+            .iter_mut()
+            .filter(|c| {
+                c.controlled_tiles
+                    .iter()
+                    .any(|t| chebyshev_distance(t.x, t.y, unit.x, unit.y) <= 1)
+            })
+            .min_by_key(|c| chebyshev_distance(c.x, c.y, unit.x, unit.y))
+            .ok_or(UnitError::NotInOwnTerritory)?;
+        if city.accumulated_food < HEAL_FOOD_COST as i32 {
+            return err!(UnitError::NotEnoughResources);
+        }
+        city.accumulated_food -= HEAL_FOOD_COST as i32;
+    }
+
+    // healing takes the whole turn of the unit
+    let unit = &mut player_account.units[unit_idx];
+    unit.health = std::cmp::min(unit.health.saturating_add(heal_amount), 100);
+    unit.movement_range = 0;
+
+    msg!("Unit #{} healed to {} HP", unit.unit_id, unit.health);
+
+    Ok(())
+}
+
+pub fn upgrade_unit(ctx: Context<UpgradeUnit>, unit_id: u32, promotion: Promotion) -> Result<()> {
     let units = &mut ctx.accounts.player_account.units;
     let unit_idx = units
         .iter()
@@ -86,7 +312,6 @@ pub fn upgrade_unit(ctx: Context<UpgradeUnit>, unit_id: u32) -> Result<()> {
 
     let unit_exp = units[unit_idx].experience;
     let unit_level = units[unit_idx].level;
-    let unit_health = units[unit_idx].health;
     let unit_movement_range = units[unit_idx].movement_range;
 
     // Unit doesn't have movement range
@@ -104,18 +329,8 @@ pub fn upgrade_unit(ctx: Context<UpgradeUnit>, unit_id: u32) -> Result<()> {
         return err!(UnitError::NotEnoughExp);
     }
 
-    // Level Up
-    if unit_health >= 70 {
-        ctx.accounts.player_account.units[unit_idx].health = 100;
-    } else {
-        ctx.accounts.player_account.units[unit_idx].health += 30;
-    }
-
-    ctx.accounts.player_account.units[unit_idx].attack += 2;
-    ctx.accounts.player_account.units[unit_idx].level += 1;
-
-    // After the upgrade, the unit cannot move or attack anymore
-    ctx.accounts.player_account.units[unit_idx].movement_range = 0;
+    // Level Up, after the upgrade the unit cannot move or attack anymore
+    ctx.accounts.player_account.units[unit_idx].promote(promotion)?;
 
     Ok(())
 }
@@ -146,7 +361,13 @@ fn calculate_controlled_tiles(x: u8, y: u8, existing_cities: &[City]) -> Vec<Til
     tiles
 }
 
-pub fn found_city(ctx: Context<FoundCity>, x: u8, y: u8, unit_id: u32, name: String) -> Result<()> {
+pub fn found_city<'info>(
+    ctx: Context<'_, '_, '_, 'info, FoundCity<'info>>,
+    x: u8,
+    y: u8,
+    unit_id: u32,
+    name: String,
+) -> Result<()> {
     // Validate if the unit with `unit_id` is a settler and is at `x` and `y`.
     let unit_idx = ctx
         .accounts
@@ -178,6 +399,11 @@ pub fn found_city(ctx: Context<FoundCity>, x: u8, y: u8, unit_id: u32, name: Str
         return err!(UnitError::WithinControlledTerritory);
     }
 
+    let terrain = ctx.accounts.game.map[(y as usize * MAP_BOUND as usize) + x as usize].terrain;
+    if !terrain.is_buildable() {
+        return err!(UnitError::NotBuildable);
+    }
+
     let controlled_tiles = calculate_controlled_tiles(x, y, &ctx.accounts.player_account.cities);
 
     // Initialize the new City.
@@ -192,15 +418,27 @@ pub fn found_city(ctx: Context<FoundCity>, x: u8, y: u8, unit_id: u32, name: Str
         controlled_tiles: controlled_tiles.clone(),
     };
 
-    let new_city = City::new(params);
+    let mut new_city = City::new(params);
+
+    // the city center works its own tile
+    let (food, production, gold) = terrain.yields();
+    new_city.food_yield += food;
+    new_city.production_yield += production;
+    new_city.gold_yield += gold;
 
     ctx.accounts.player_account.cities.push(new_city);
 
     // Mark controlled tiles as discovered
-    for tile_coord in controlled_tiles {
-        let tile_index = (tile_coord.y as usize * MAP_BOUND as usize) + tile_coord.x as usize;
-        ctx.accounts.game.map[tile_index].discovered = true;
-    }
+    let player_key = ctx.accounts.player_account.player;
+    let mut other_players =
+        load_other_players(&ctx.accounts.game, &player_key, ctx.remaining_accounts)?;
+    discover_tiles(
+        ctx.program_id,
+        &ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &mut other_players,
+        &controlled_tiles,
+    )?;
 
     // Remove the settler unit used to found the city.
     ctx.accounts.player_account.units.remove(unit_idx);
@@ -240,10 +478,10 @@ pub fn upgrade_tile(ctx: Context<UpgradeTile>, x: u8, y: u8, unit_id: u32) -> Re
 
     // Check if the tile type is upgradeable and the tile is not occupied by a City or another Tile.
     let map_idx = (y as usize) * MAP_BOUND as usize + x as usize;
-    match ctx.accounts.game.map[map_idx].terrain {
-        1 | 2 | 5 | 6 | 7 => {} // allowable tile types
-        _ => return err!(TileError::NotUpgradeable),
-    }
+    let tile_type = ctx.accounts.game.map[map_idx]
+        .terrain
+        .improvement()
+        .ok_or(TileError::NotUpgradeable)?;
 
     if ctx
         .accounts
@@ -276,16 +514,6 @@ pub fn upgrade_tile(ctx: Context<UpgradeTile>, x: u8, y: u8, unit_id: u32) -> Re
         .ok_or(TileError::TileNotControlled)?;
 
     // Initialize the new Tile and push it to player_account tiles vector.
-    let tile_type = match ctx.accounts.game.map[map_idx].terrain {
-        1 => TileType::IronMine,
-        2 => TileType::LumberMill,
-        5 => TileType::StoneQuarry,
-        6 => TileType::Farm,
-        7 => TileType::Pasture,
-        // we've already checked the tile type above, if there was no match, we would have returned an error NotUpgradeable
-        _ => unreachable!(),
-    };
-
     let new_tile = Tile::new(tile_type, x, y);
     ctx.accounts.player_account.tiles.push(new_tile);
 
@@ -313,6 +541,16 @@ pub fn upgrade_tile(ctx: Context<UpgradeTile>, x: u8, y: u8, unit_id: u32) -> Re
 }
 
 pub fn attack_unit(ctx: Context<AttackUnit>, attacker_id: u32, defender_id: u32) -> Result<()> {
+    let random_factor = draw_combat_random_factor(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[
+            b"attack_unit",
+            &attacker_id.to_le_bytes(),
+            &defender_id.to_le_bytes(),
+        ],
+        &ctx.accounts.recent_slothashes,
+    )?;
     let attacker = ctx
         .accounts
         .player_account
@@ -320,31 +558,48 @@ pub fn attack_unit(ctx: Context<AttackUnit>, attacker_id: u32, defender_id: u32)
         .iter_mut()
         .find(|u| u.unit_id == attacker_id)
         .ok_or(UnitError::UnitNotFound)?;
-    let defender = ctx
-        .accounts
-        .npc_account
-        .units
-        .iter_mut()
-        .find(|u| u.unit_id == defender_id)
+    let npc_units = &mut ctx.accounts.npc_account.units;
+    let defender_idx = npc_units
+        .iter()
+        .position(|u| u.unit_id == defender_id)
         .ok_or(UnitError::UnitNotFound)?;
+    // a military unit covers the civilian stacked with it
+    let defender_idx = tile_defender_index(
+        npc_units,
+        npc_units[defender_idx].x,
+        npc_units[defender_idx].y,
+    )
+    .unwrap_or(defender_idx);
+    let defender = &mut npc_units[defender_idx];
 
     if attacker.movement_range == 0 {
         return err!(UnitError::NoMovementPoints);
     }
 
-    // Check proximity (attacker should be 1 tile away from defender)
+    // Check proximity (melee attacker should be 1 tile away from defender, ranged up to 2 tiles)
     // Chebyshev Distance:
     let dist_x = (attacker.x as i16 - defender.x as i16).abs();
     let dist_y = (attacker.y as i16 - defender.y as i16).abs();
     let dist = std::cmp::max(dist_x, dist_y) as u8;
 
-    if dist != 1 {
+    if dist == 0 || dist > attacker.get_attack_range() {
         return err!(UnitError::OutOfAttackRange);
     }
 
-    attacker.attack_unit(defender, None)?;
-
-    if !defender.is_alive {
+    // the attack is committed first and fought in a later slot
+    let Some(random_factor) = random_factor else {
+        return Ok(());
+    };
+    let modifiers = CombatModifiers::new(&ctx.accounts.game.map, attacker, defender, false);
+    attacker.attack_unit(defender, modifiers, random_factor)?;
+
+    if defender.is_captured() {
+        // civilians held by barbarians are rescued
+        let player_account = &mut ctx.accounts.player_account;
+        let rescued = defender.captured_by(player_account.next_unit_id, player_account.player);
+        player_account.units.push(rescued);
+        player_account.next_unit_id += 1;
+    } else if !defender.is_alive {
         ctx.accounts.player_account.resources.gems = ctx
             .accounts
             .player_account
@@ -362,6 +617,16 @@ pub fn attack_unit(ctx: Context<AttackUnit>, attacker_id: u32, defender_id: u32)
 }
 
 pub fn attack_city(ctx: Context<AttackCity>, attacker_id: u32, city_id: u32) -> Result<()> {
+    let random_factor = draw_combat_random_factor(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[
+            b"attack_city",
+            &attacker_id.to_le_bytes(),
+            &city_id.to_le_bytes(),
+        ],
+        &ctx.accounts.recent_slothashes,
+    )?;
     let attacker = ctx
         .accounts
         .player_account
@@ -386,39 +651,369 @@ pub fn attack_city(ctx: Context<AttackCity>, attacker_id: u32, city_id: u32) ->
     let dist_y = (attacker.y as i16 - target_city.y as i16).abs();
     let dist = std::cmp::max(dist_x, dist_y) as u8;
 
-    if dist != 1 {
+    if dist == 0 || dist > attacker.get_attack_range() {
         return err!(UnitError::OutOfAttackRange);
     }
 
-    attacker.attack_city(target_city)?;
+    // the attack is committed first and fought in a later slot
+    let Some(random_factor) = random_factor else {
+        return Ok(());
+    };
+    attacker.attack_city(target_city, random_factor)?;
     attacker.movement_range = 0;
     attacker.experience = get_new_exp(attacker.level, attacker.experience, 3);
 
-    let city_was_destroyed = target_city.health == 0;
+    // melee unit that defeats the city moves in, unless barbarian units still stay there
+    let (city_x, city_y) = (target_city.x, target_city.y);
+    let city_was_captured = target_city.health == 0
+        && attacker.can_capture_city()
+        && !ctx
+            .accounts
+            .npc_account
+            .units
+            .iter()
+            .any(|u| u.x == city_x && u.y == city_y && u.is_alive);
+
+    if city_was_captured {
+        attacker.x = city_x;
+        attacker.y = city_y;
+
+        let city_idx = ctx
+            .accounts
+            .npc_account
+            .cities
+            .iter()
+            .position(|c| c.city_id == city_id)
+            .ok_or(CityError::CityNotFound)?;
+        let city = ctx.accounts.npc_account.cities.remove(city_idx);
+        let player_account = &mut ctx.accounts.player_account;
+        let captured_city = city.captured_by(
+            player_account.next_city_id,
+            player_account.player,
+            &mut ctx.accounts.game.randomness,
+        );
+        msg!("City #{} captured", captured_city.city_id);
+        player_account.cities.push(captured_city);
+        player_account.next_city_id += 1;
+
+        player_account.resources.gems = player_account.resources.gems.saturating_add(
+            GEMS_PER_CITY_DESTROYED[ctx.accounts.game.difficulty_level as usize] as u32,
+        );
+    }
+
+    ctx.accounts.player_account.units.retain(|u| u.is_alive);
+
+    Ok(())
+}
+
+pub fn attack_player_unit(
+    ctx: Context<AttackPlayerUnit>,
+    attacker_id: u32,
+    defender_id: u32,
+) -> Result<()> {
+    if !ctx.accounts.game.is_at_war(
+        &ctx.accounts.player_account.player,
+        &ctx.accounts.defender_account.player,
+    ) {
+        return err!(DiplomacyError::NotAtWar);
+    }
+    let random_factor = draw_combat_random_factor(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[
+            b"attack_player_unit",
+            &attacker_id.to_le_bytes(),
+            &defender_id.to_le_bytes(),
+        ],
+        &ctx.accounts.recent_slothashes,
+    )?;
+
+    let defender_account = &mut ctx.accounts.defender_account;
+    let defender_idx = defender_account
+        .units
+        .iter()
+        .position(|u| u.unit_id == defender_id)
+        .ok_or(UnitError::UnitNotFound)?;
+    let (defender_x, defender_y) = (
+        defender_account.units[defender_idx].x,
+        defender_account.units[defender_idx].y,
+    );
+    // a military unit covers the civilian stacked with it
+    let defender_idx = tile_defender_index(&defender_account.units, defender_x, defender_y)
+        .unwrap_or(defender_idx);
+
+    // defender that stays in its own city with a wall takes less damage
+    let defender_behind_the_wall = defender_account
+        .cities
+        .iter()
+        .any(|c| c.x == defender_x && c.y == defender_y && c.wall_health > 0);
+
+    let attacker = ctx
+        .accounts
+        .player_account
+        .units
+        .iter_mut()
+        .find(|u| u.unit_id == attacker_id)
+        .ok_or(UnitError::UnitNotFound)?;
+    let defender = &mut defender_account.units[defender_idx];
+
+    if attacker.movement_range == 0 {
+        return err!(UnitError::NoMovementPoints);
+    }
+
+    // Check proximity (melee attacker should be 1 tile away from defender, ranged up to 2 tiles)
+    // Chebyshev Distance:
+    let dist_x = (attacker.x as i16 - defender.x as i16).abs();
+    let dist_y = (attacker.y as i16 - defender.y as i16).abs();
+    let dist = std::cmp::max(dist_x, dist_y) as u8;
+
+    if dist == 0 || dist > attacker.get_attack_range() {
+        return err!(UnitError::OutOfAttackRange);
+    }
 
-    if city_was_destroyed {
+    // the attack is committed first and fought in a later slot
+    let Some(random_factor) = random_factor else {
+        return Ok(());
+    };
+    let modifiers = CombatModifiers::new(
+        &ctx.accounts.game.map,
+        attacker,
+        defender,
+        defender_behind_the_wall,
+    );
+    attacker.attack_unit(defender, modifiers, random_factor)?;
+
+    let defender_was_captured = defender.is_captured();
+    let defender_was_killed = !defender.is_alive && !defender_was_captured;
+    let attacker_was_killed = !attacker.is_alive;
+    let gems_per_kill = GEMS_PER_KILL[ctx.accounts.game.difficulty_level as usize] as u32;
+
+    if defender_was_captured {
+        let player_account = &mut ctx.accounts.player_account;
+        let captured = defender.captured_by(player_account.next_unit_id, player_account.player);
+        player_account.units.push(captured);
+        player_account.next_unit_id += 1;
+    }
+    if defender_was_killed {
         ctx.accounts.player_account.resources.gems = ctx
             .accounts
             .player_account
             .resources
             .gems
-            .checked_add(
-                GEMS_PER_CITY_DESTROYED[ctx.accounts.game.difficulty_level as usize] as u32,
-            )
-            .unwrap_or(u32::MAX);
+            .saturating_add(gems_per_kill);
+    }
+    if attacker_was_killed {
+        ctx.accounts.defender_account.resources.gems = ctx
+            .accounts
+            .defender_account
+            .resources
+            .gems
+            .saturating_add(gems_per_kill);
     }
 
+    // Retain only alive units in the game
     ctx.accounts.player_account.units.retain(|u| u.is_alive);
-    ctx.accounts.npc_account.cities.retain(|c| c.health > 0);
+    ctx.accounts.defender_account.units.retain(|u| u.is_alive);
 
     Ok(())
 }
 
+pub fn attack_player_city(
+    ctx: Context<AttackPlayerCity>,
+    attacker_id: u32,
+    city_id: u32,
+) -> Result<()> {
+    if !ctx.accounts.game.is_at_war(
+        &ctx.accounts.player_account.player,
+        &ctx.accounts.defender_account.player,
+    ) {
+        return err!(DiplomacyError::NotAtWar);
+    }
+    let random_factor = draw_combat_random_factor(
+        &mut ctx.accounts.game,
+        &mut ctx.accounts.player_account,
+        &[
+            b"attack_player_city",
+            &attacker_id.to_le_bytes(),
+            &city_id.to_le_bytes(),
+        ],
+        &ctx.accounts.recent_slothashes,
+    )?;
+
+    let attacker = ctx
+        .accounts
+        .player_account
+        .units
+        .iter_mut()
+        .find(|u| u.unit_id == attacker_id)
+        .ok_or(UnitError::UnitNotFound)?;
+
+    if attacker.movement_range == 0 {
+        return err!(UnitError::NoMovementPoints);
+    }
+
+    let target_city = ctx
+        .accounts
+        .defender_account
+        .cities
+        .iter_mut()
+        .find(|c| c.city_id == city_id)
+        .ok_or(CityError::CityNotFound)?;
+
+    let dist_x = (attacker.x as i16 - target_city.x as i16).abs();
+    let dist_y = (attacker.y as i16 - target_city.y as i16).abs();
+    let dist = std::cmp::max(dist_x, dist_y) as u8;
+
+    if dist == 0 || dist > attacker.get_attack_range() {
+        return err!(UnitError::OutOfAttackRange);
+    }
+
+    // the attack is committed first and fought in a later slot
+    let Some(random_factor) = random_factor else {
+        return Ok(());
+    };
+    attacker.attack_city(target_city, random_factor)?;
+    attacker.movement_range = 0;
+    attacker.experience = get_new_exp(attacker.level, attacker.experience, EXP_PER_ATTACK);
+
+    // melee unit that defeats the city moves in, unless defending units still stay there
+    let (city_x, city_y) = (target_city.x, target_city.y);
+    let city_was_captured = target_city.health == 0
+        && attacker.can_capture_city()
+        && !ctx
+            .accounts
+            .defender_account
+            .units
+            .iter()
+            .any(|u| u.x == city_x && u.y == city_y && u.is_alive);
+    let attacker_was_killed = !attacker.is_alive;
+
+    if city_was_captured {
+        attacker.x = city_x;
+        attacker.y = city_y;
+
+        let city_idx = ctx
+            .accounts
+            .defender_account
+            .cities
+            .iter()
+            .position(|c| c.city_id == city_id)
+            .ok_or(CityError::CityNotFound)?;
+        let city = ctx.accounts.defender_account.cities.remove(city_idx);
+        let player_account = &mut ctx.accounts.player_account;
+        let captured_city = city.captured_by(
+            player_account.next_city_id,
+            player_account.player,
+            &mut ctx.accounts.game.randomness,
+        );
+        msg!("City #{} captured", captured_city.city_id);
+        player_account.cities.push(captured_city);
+        player_account.next_city_id += 1;
+    }
+    if attacker_was_killed {
+        ctx.accounts.defender_account.resources.gems = ctx
+            .accounts
+            .defender_account
+            .resources
+            .gems
+            .saturating_add(GEMS_PER_KILL[ctx.accounts.game.difficulty_level as usize] as u32);
+    }
+
+    ctx.accounts.player_account.units.retain(|u| u.is_alive);
+
+    Ok(())
+}
+
+/// Simulates the attack with the lowest and the highest random factor without changing any account.
+/// The result is returned to the client via return data.
+pub fn preview_attack(
+    ctx: Context<PreviewAttack>,
+    attacker_id: u32,
+    target: AttackTarget,
+) -> Result<AttackPreview> {
+    let attacker = *ctx
+        .accounts
+        .player_account
+        .units
+        .iter()
+        .find(|u| u.unit_id == attacker_id)
+        .ok_or(UnitError::UnitNotFound)?;
+
+    // barbarians are previewed via npc_account, other civilizations via defender_account
+    let (units, cities, is_npc) = match (&ctx.accounts.npc_account, &ctx.accounts.defender_account)
+    {
+        (Some(npc_account), _) => (&npc_account.units, &npc_account.cities, true),
+        (None, Some(defender_account)) => {
+            (&defender_account.units, &defender_account.cities, false)
+        }
+        (None, None) => return err!(UnitError::InvalidAttack),
+    };
+
+    let mut preview = AttackPreview {
+        min_given_damage: u32::MAX,
+        min_taken_damage: u32::MAX,
+        ..Default::default()
+    };
+
+    for random_factor in [0, COMBAT_RANDOM_RANGE as u8 - 1] {
+        let mut attacker_copy = attacker;
+        let (given_damage, defender_died) = match target {
+            AttackTarget::Unit(defender_id) => {
+                let target = units
+                    .iter()
+                    .find(|u| u.unit_id == defender_id)
+                    .ok_or(UnitError::UnitNotFound)?;
+                let mut defender = tile_defender_index(units, target.x, target.y)
+                    .map_or(*target, |idx| units[idx]);
+                // same as in attack_player_unit, only other civilizations hide behind their walls
+                let defender_behind_the_wall = !is_npc
+                    && cities
+                        .iter()
+                        .any(|c| c.x == defender.x && c.y == defender.y && c.wall_health > 0);
+                let modifiers = CombatModifiers::new(
+                    &ctx.accounts.game.map,
+                    &attacker_copy,
+                    &defender,
+                    defender_behind_the_wall,
+                );
+                let health_before = defender.health;
+                attacker_copy.attack_unit(&mut defender, modifiers, random_factor)?;
+                (
+                    health_before.saturating_sub(defender.health) as u32,
+                    !defender.is_alive,
+                )
+            }
+            AttackTarget::City(city_id) => {
+                let mut city = cities
+                    .iter()
+                    .find(|c| c.city_id == city_id)
+                    .ok_or(CityError::CityNotFound)?
+                    .clone();
+                let health_before = city.health + city.wall_health;
+                attacker_copy.attack_city(&mut city, random_factor)?;
+                (
+                    health_before - city.health - city.wall_health,
+                    city.health == 0,
+                )
+            }
+        };
+        preview.record(given_damage, attacker.health, &attacker_copy, defender_died);
+    }
+
+    Ok(preview)
+}
+
 #[derive(Accounts)]
 pub struct FoundCity<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Account<'info, Game>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -427,17 +1022,66 @@ pub struct FoundCity<'info> {
 
 #[derive(Accounts)]
 pub struct MoveUnit<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Box<Account<'info, Game>>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
+    #[account(has_one = game)]
+    pub npc_account: Account<'info, Npc>,
+    pub session_token: Option<Account<'info, SessionToken>>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
 
 #[derive(Accounts)]
 pub struct HealUnit<'info> {
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpgradeUnitType<'info> {
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DisbandUnit<'info> {
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetStance<'info> {
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -445,7 +1089,11 @@ pub struct HealUnit<'info> {
 
 #[derive(Accounts)]
 pub struct UpgradeUnit<'info> {
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -453,9 +1101,15 @@ pub struct UpgradeUnit<'info> {
 
 #[derive(Accounts)]
 pub struct UpgradeTile<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Box<Account<'info, Game>>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = player,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.pending_commitment.is_none() @ GameError::CommitmentPending
+    )]
     pub player_account: Account<'info, Player>,
     #[account(mut)]
     pub player: Signer<'info>,
@@ -463,24 +1117,103 @@ pub struct UpgradeTile<'info> {
 
 #[derive(Accounts)]
 pub struct AttackUnit<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Box<Account<'info, Game>>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
     pub player_account: Account<'info, Player>,
-    #[account(mut, has_one = player)]
+    #[account(mut, has_one = game)]
     pub npc_account: Account<'info, Npc>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
 
 #[derive(Accounts)]
 pub struct AttackCity<'info> {
-    #[account(mut, has_one = player)]
+    #[account(mut)]
     pub game: Box<Account<'info, Game>>,
-    #[account(mut, has_one = player)]
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
     pub player_account: Account<'info, Player>,
-    #[account(mut, has_one = player)]
+    #[account(mut, has_one = game)]
     pub npc_account: Account<'info, Npc>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AttackPlayerUnit<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = defender_account.key() != player_account.key() @ UnitError::InvalidAttack
+    )]
+    pub defender_account: Account<'info, Player>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
     #[account(mut)]
     pub player: Signer<'info>,
 }
+
+#[derive(Accounts)]
+pub struct AttackPlayerCity<'info> {
+    #[account(mut)]
+    pub game: Box<Account<'info, Game>>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = game.is_current_player(&player_account.player) @ GameError::NotYourTurn,
+        constraint = player_account.is_authorized(&player.key(), session_token.as_deref(), game.turn) @ SessionError::InvalidSigner
+    )]
+    pub player_account: Account<'info, Player>,
+    #[account(
+        mut,
+        has_one = game,
+        constraint = defender_account.key() != player_account.key() @ UnitError::InvalidAttack
+    )]
+    pub defender_account: Account<'info, Player>,
+    pub session_token: Option<Account<'info, SessionToken>>,
+    /// CHECK: SlotHashes sysvar used as an entropy source, the address is checked by the constraint
+    #[account(address = slot_hashes::ID)]
+    pub recent_slothashes: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub player: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewAttack<'info> {
+    pub game: Box<Account<'info, Game>>,
+    #[account(has_one = game)]
+    pub player_account: Account<'info, Player>,
+    #[account(has_one = game)]
+    pub npc_account: Option<Account<'info, Npc>>,
+    #[account(has_one = game)]
+    pub defender_account: Option<Account<'info, Player>>,
+}
@@ -1,13 +1,16 @@
 #![allow(clippy::result_large_err)]
 
-mod consts;
-mod errors;
+pub mod consts;
+pub mod errors;
 mod instructions;
-mod state;
-mod utils;
+pub mod state;
+pub mod utils;
 
 use crate::instructions::*;
-use crate::state::{ProductionItem, TechnologyType, TileCoordinate};
+use crate::state::{
+    AttackPreview, AttackTarget, ProductionItem, Promotion, TechnologyType, TileCoordinate,
+    UnitStance,
+};
 use anchor_lang::prelude::*;
 
 declare_id!("3qoyRXbpBJDPfQYL5GUFJ2nf2YzpA8kZmXPYr4DZBmPU");
@@ -31,6 +34,14 @@ pub mod solciv {
         instructions::initialize_player(ctx, position)
     }
 
+    pub fn join_game(ctx: Context<JoinGame>, position: TileCoordinate) -> Result<()> {
+        instructions::join_game(ctx, position)
+    }
+
+    pub fn leave_game(ctx: Context<LeaveGame>) -> Result<()> {
+        instructions::leave_game(ctx)
+    }
+
     pub fn initialize_npc(
         ctx: Context<InitializeNpc>,
         npc_position_1: TileCoordinate,
@@ -39,16 +50,41 @@ pub mod solciv {
         instructions::initialize_npc(ctx, npc_position_1, npc_position_2)
     }
 
-    pub fn move_unit(ctx: Context<MoveUnit>, unit_id: u32, x: u8, y: u8) -> Result<()> {
+    pub fn move_unit<'info>(
+        ctx: Context<'_, '_, '_, 'info, MoveUnit<'info>>,
+        unit_id: u32,
+        x: u8,
+        y: u8,
+    ) -> Result<()> {
         instructions::move_unit(ctx, unit_id, x, y)
     }
 
-    pub fn upgrade_unit(ctx: Context<UpgradeUnit>, unit_id: u32) -> Result<()> {
-        instructions::upgrade_unit(ctx, unit_id)
+    pub fn set_stance(ctx: Context<SetStance>, unit_id: u32, stance: UnitStance) -> Result<()> {
+        instructions::set_stance(ctx, unit_id, stance)
+    }
+
+    pub fn heal_unit(ctx: Context<HealUnit>, unit_id: u32) -> Result<()> {
+        instructions::heal_unit(ctx, unit_id)
+    }
+
+    pub fn upgrade_unit(
+        ctx: Context<UpgradeUnit>,
+        unit_id: u32,
+        promotion: Promotion,
+    ) -> Result<()> {
+        instructions::upgrade_unit(ctx, unit_id, promotion)
+    }
+
+    pub fn upgrade_unit_type(ctx: Context<UpgradeUnitType>, unit_id: u32) -> Result<()> {
+        instructions::upgrade_unit_type(ctx, unit_id)
     }
 
-    pub fn found_city(
-        ctx: Context<FoundCity>,
+    pub fn disband_unit(ctx: Context<DisbandUnit>, unit_id: u32) -> Result<()> {
+        instructions::disband_unit(ctx, unit_id)
+    }
+
+    pub fn found_city<'info>(
+        ctx: Context<'_, '_, '_, 'info, FoundCity<'info>>,
         x: u8,
         y: u8,
         unit_id: u32,
@@ -100,6 +136,70 @@ pub mod solciv {
         instructions::attack_city(ctx, attacker_id, city_id)
     }
 
+    pub fn attack_player_unit(
+        ctx: Context<AttackPlayerUnit>,
+        attacker_id: u32,
+        defender_id: u32,
+    ) -> Result<()> {
+        instructions::attack_player_unit(ctx, attacker_id, defender_id)
+    }
+
+    pub fn attack_player_city(
+        ctx: Context<AttackPlayerCity>,
+        attacker_id: u32,
+        city_id: u32,
+    ) -> Result<()> {
+        instructions::attack_player_city(ctx, attacker_id, city_id)
+    }
+
+    pub fn city_strike(ctx: Context<CityStrike>, city_id: u32, unit_id: u32) -> Result<()> {
+        instructions::city_strike(ctx, city_id, unit_id)
+    }
+
+    pub fn preview_attack(
+        ctx: Context<PreviewAttack>,
+        attacker_id: u32,
+        target: AttackTarget,
+    ) -> Result<AttackPreview> {
+        instructions::preview_attack(ctx, attacker_id, target)
+    }
+
+    pub fn declare_war(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+        instructions::declare_war(ctx, civilization)
+    }
+
+    pub fn propose_peace(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+        instructions::propose_peace(ctx, civilization)
+    }
+
+    pub fn accept_peace(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+        instructions::accept_peace(ctx, civilization)
+    }
+
+    pub fn propose_alliance(ctx: Context<UpdateRelations>, civilization: Pubkey) -> Result<()> {
+        instructions::propose_alliance(ctx, civilization)
+    }
+
+    pub fn accept_alliance(ctx: Context<AllianceVision>) -> Result<()> {
+        instructions::accept_alliance(ctx)
+    }
+
+    pub fn exchange_maps(ctx: Context<AllianceVision>) -> Result<()> {
+        instructions::exchange_maps(ctx)
+    }
+
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_key: Pubkey,
+        turns: u32,
+    ) -> Result<()> {
+        instructions::create_session(ctx, session_key, turns)
+    }
+
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        instructions::revoke_session(ctx)
+    }
+
     pub fn create_gems(
         ctx: Context<CreateGems>,
         token_name: String,
@@ -113,10 +213,14 @@ pub mod solciv {
         instructions::mint_gems(ctx)
     }
 
-    pub fn end_turn(ctx: Context<EndTurn>) -> Result<()> {
+    pub fn end_turn<'info>(ctx: Context<'_, '_, '_, 'info, EndTurn<'info>>) -> Result<()> {
         instructions::end_turn(ctx)
     }
 
+    pub fn skip_turn(ctx: Context<SkipTurn>) -> Result<()> {
+        instructions::skip_turn(ctx)
+    }
+
     pub fn close_game(ctx: Context<Close>) -> Result<()> {
         instructions::close_game(ctx)
     }
@@ -124,4 +228,12 @@ pub mod solciv {
     pub fn repair_wall(ctx: Context<RepairWall>, city_id: u32) -> Result<()> {
         instructions::repair_wall(ctx, city_id)
     }
+
+    pub fn annex_city(ctx: Context<AnnexCity>, city_id: u32) -> Result<()> {
+        instructions::annex_city(ctx, city_id)
+    }
+
+    pub fn raze_city(ctx: Context<RazeCity>, city_id: u32) -> Result<()> {
+        instructions::raze_city(ctx, city_id)
+    }
 }
@@ -1,4 +1,6 @@
-use crate::state::{TechnologyType, UnitType};
+use crate::consts::*;
+use crate::state::{Randomness, TechnologyType, Unit, UnitType};
+use crate::utils::*;
 use anchor_lang::prelude::*;
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
@@ -25,6 +27,18 @@ pub struct City {
     pub controlled_tiles: Vec<TileCoordinate>,
     pub level: u32,
     pub growth_points: u32,
+    pub has_struck: bool,
+    pub status: CityStatus,
+}
+
+/// Captured city waits for its conqueror to annex or raze it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum CityStatus {
+    Normal,
+    /// captured, no production until annexed or razed
+    Occupied,
+    /// loses one citizen every turn and is removed once empty
+    Razing,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Hash)]
@@ -99,6 +113,8 @@ impl City {
             housing: 4,
             level: 0,
             growth_points: 0,
+            has_struck: false,
+            status: CityStatus::Normal,
         }
     }
 
@@ -108,6 +124,28 @@ impl City {
             .any(|tile| tile.x == tile_x && tile.y == tile_y)
     }
 
+    /// Food produced by the city after feeding its citizens, 2 food per citizen.
+    pub fn food_surplus(&self) -> i32 {
+        self.food_yield as i32 - (self.population * 2) as i32
+    }
+
+    /// Only cities with walls can bombard enemy units, once per turn.
+    pub fn can_strike(&self) -> bool {
+        self.wall_health > 0 && !self.has_struck
+    }
+
+    /// Damage of a ranged city strike against `target`, the target does not strike back.
+    pub fn strike_damage(&self, target: &Unit, random_factor: u8) -> u8 {
+        // same formula as a unit attack, with the city attack as combat strength
+        let target_strength = target.attack.saturating_add(target.fortify_bonus());
+        let strength_diff = self.attack as i32 - target_strength as i32;
+        fixed_to_damage(scaled_damage(
+            30,
+            strength_diff,
+            damage_multiplier(random_factor),
+        ))
+    }
+
     pub fn construct_building(&mut self, building_type: BuildingType) -> Result<()> {
         match building_type {
             BuildingType::Barracks => {
@@ -156,6 +194,88 @@ impl City {
 
         Ok(())
     }
+
+    /// Removes the building and reverts its effects on the city.
+    pub fn remove_building(&mut self, building_type: BuildingType) {
+        let Some(idx) = self.buildings.iter().position(|b| *b == building_type) else {
+            return;
+        };
+        self.buildings.remove(idx);
+        match building_type {
+            BuildingType::Barracks => {
+                self.attack = self.attack.saturating_sub(2);
+                self.housing = self.housing.saturating_sub(1);
+            }
+            BuildingType::Wall | BuildingType::WallMedieval => {
+                self.attack = self.attack.saturating_sub(5);
+                self.wall_health = 0;
+            }
+            BuildingType::WallRenaissance | BuildingType::WallIndustrial => {
+                self.attack = self.attack.saturating_sub(10);
+                self.wall_health = 0;
+            }
+            BuildingType::Library => self.science_yield = self.science_yield.saturating_sub(2),
+            BuildingType::School => self.science_yield = self.science_yield.saturating_sub(3),
+            BuildingType::University => {
+                self.science_yield = self.science_yield.saturating_sub(4);
+                self.housing = self.housing.saturating_sub(1);
+            }
+            BuildingType::Observatory => self.science_yield = self.science_yield.saturating_sub(5),
+            BuildingType::Forge => self.production_yield = self.production_yield.saturating_sub(2),
+            BuildingType::Factory => {
+                self.production_yield = self.production_yield.saturating_sub(3)
+            }
+            BuildingType::EnergyPlant => {
+                self.production_yield = self.production_yield.saturating_sub(4)
+            }
+            BuildingType::Market => self.gold_yield = self.gold_yield.saturating_sub(2),
+            BuildingType::Bank => self.gold_yield = self.gold_yield.saturating_sub(3),
+            BuildingType::StockExchange => self.gold_yield = self.gold_yield.saturating_sub(4),
+            BuildingType::Granary => {
+                self.food_yield = self.food_yield.saturating_sub(2);
+                self.housing = self.housing.saturating_sub(2);
+            }
+            BuildingType::Mill => self.food_yield = self.food_yield.saturating_sub(2),
+            BuildingType::Bakery => self.food_yield = self.food_yield.saturating_sub(3),
+            BuildingType::Supermarket => self.food_yield = self.food_yield.saturating_sub(4),
+            BuildingType::ResidentialComplex => self.housing = self.housing.saturating_sub(5),
+        }
+    }
+
+    /// Only cities in the normal state work on their production queue.
+    pub fn can_produce(&self) -> bool {
+        self.status == CityStatus::Normal
+    }
+
+    /// Returns the city taken over by the `player` with a new id.
+    /// Half of the citizens leave, the walls are torn down and every other building survives with a 50% chance.
+    pub fn captured_by(&self, city_id: u32, player: Pubkey, randomness: &mut Randomness) -> City {
+        let mut city = City {
+            city_id,
+            player,
+            health: CAPTURED_CITY_HEALTH,
+            population: std::cmp::max(self.population / 2, 1),
+            production_queue: vec![],
+            accumulated_production: 0,
+            accumulated_food: 0,
+            has_struck: false,
+            status: CityStatus::Occupied,
+            ..self.clone()
+        };
+        for building in self.buildings.iter() {
+            let is_wall = matches!(
+                building,
+                BuildingType::Wall
+                    | BuildingType::WallMedieval
+                    | BuildingType::WallRenaissance
+                    | BuildingType::WallIndustrial
+            );
+            if is_wall || randomness.next_below(2) == 0 {
+                city.remove_building(*building);
+            }
+        }
+        city
+    }
 }
 
 impl BuildingType {
@@ -0,0 +1,88 @@
+use crate::consts::MAP_BOUND;
+use crate::state::{Game, Player};
+use anchor_lang::prelude::*;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub enum DiplomaticStatus {
+    Peace,
+    War,
+    Alliance,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Proposal {
+    pub proposed_by: Pubkey,
+    pub status: DiplomaticStatus,
+}
+
+/// Relationship between two civilizations of the same game.
+/// Pairs without a stored relation are at peace.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Relation {
+    pub civilizations: [Pubkey; 2],
+    pub status: DiplomaticStatus,
+    pub proposal: Option<Proposal>,
+}
+
+impl Relation {
+    fn is_between(&self, a: &Pubkey, b: &Pubkey) -> bool {
+        (self.civilizations[0] == *a && self.civilizations[1] == *b)
+            || (self.civilizations[0] == *b && self.civilizations[1] == *a)
+    }
+
+    pub fn involves(&self, civilization: &Pubkey) -> bool {
+        self.civilizations.contains(civilization)
+    }
+}
+
+impl Game {
+    pub fn relation(&self, a: &Pubkey, b: &Pubkey) -> Option<&Relation> {
+        self.relations.iter().find(|r| r.is_between(a, b))
+    }
+
+    /// Returns the relation between two civilizations, creating a peaceful one if it doesn't exist yet.
+    pub fn relation_mut(&mut self, a: &Pubkey, b: &Pubkey) -> &mut Relation {
+        let index = match self.relations.iter().position(|r| r.is_between(a, b)) {
+            Some(index) => index,
+            None => {
+                self.relations.push(Relation {
+                    civilizations: [*a, *b],
+                    status: DiplomaticStatus::Peace,
+                    proposal: None,
+                });
+                self.relations.len() - 1
+            }
+        };
+        &mut self.relations[index]
+    }
+
+    pub fn diplomatic_status(&self, a: &Pubkey, b: &Pubkey) -> DiplomaticStatus {
+        self.relation(a, b)
+            .map(|r| r.status)
+            .unwrap_or(DiplomaticStatus::Peace)
+    }
+
+    pub fn is_at_war(&self, a: &Pubkey, b: &Pubkey) -> bool {
+        self.diplomatic_status(a, b) == DiplomaticStatus::War
+    }
+
+    pub fn are_allies(&self, a: &Pubkey, b: &Pubkey) -> bool {
+        self.diplomatic_status(a, b) == DiplomaticStatus::Alliance
+    }
+}
+
+impl Player {
+    pub fn discover_tile(&mut self, x: u8, y: u8) {
+        let index = y as usize * MAP_BOUND as usize + x as usize;
+        self.discovered_tiles[index / 8] |= 1 << (index % 8);
+    }
+
+    /// Both allies end up with every tile discovered by either of them so far.
+    pub fn exchange_maps(&mut self, ally: &mut Player) {
+        for i in 0..self.discovered_tiles.len() {
+            let shared = self.discovered_tiles[i] | ally.discovered_tiles[i];
+            self.discovered_tiles[i] = shared;
+            ally.discovered_tiles[i] = shared;
+        }
+    }
+}
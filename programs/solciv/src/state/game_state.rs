@@ -1,10 +1,16 @@
-use crate::consts::STORAGE_CAPACITY;
+use crate::consts::{
+    FOOD_STORAGE_CAPACITY, GRANARY_FOOD_CAPACITY, MAX_ACTIVE_GAMES, STORAGE_CAPACITY,
+};
 use crate::errors::*;
-use crate::state::{BuildingType, City, Resources, TechnologyType, Tile, Unit};
+use crate::state::{
+    BuildingType, City, Commitment, Randomness, Relation, Resources, TechnologyType, Tile,
+    TileType, Unit,
+};
 use anchor_lang::prelude::*;
 
 #[account]
 pub struct Game {
+    pub game_id: u64,
     pub player: Pubkey,
     pub npc: Pubkey,
     pub turn: u32,
@@ -12,12 +18,133 @@ pub struct Game {
     pub victory: bool,
     pub map: [Terrain; 400],
     pub difficulty_level: u8,
+    pub players: Vec<Pubkey>,
+    pub current_player_index: u8,
+    /// unix timestamp of the moment the current player got the turn
+    pub turn_started_at: i64,
+    pub relations: Vec<Relation>,
+    pub randomness: Randomness,
+}
+
+/// Per-wallet list of active games, so a player can keep several games going in parallel.
+#[account]
+pub struct GameRegistry {
+    pub player: Pubkey,
+    pub next_game_id: u64,
+    pub games: Vec<Pubkey>,
+}
+
+impl GameRegistry {
+    pub fn add_game(&mut self, game: Pubkey) -> Result<()> {
+        if self.games.len() >= MAX_ACTIVE_GAMES as usize {
+            return err!(GameError::TooManyActiveGames);
+        }
+        self.games.push(game);
+        Ok(())
+    }
+
+    pub fn remove_game(&mut self, game: &Pubkey) {
+        self.games.retain(|g| g != game);
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct Terrain {
-    pub terrain: u8,
-    pub discovered: bool,
+    pub terrain: TerrainType,
+    pub river: bool,
+}
+
+/// Terrain of a map tile. The variant index is the terrain code used by the client map.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TerrainType {
+    Grassland,
+    Iron,
+    Forest,
+    Hills,
+    Mountains,
+    Stone,
+    Plains,
+    Pasture,
+    Desert,
+    Water,
+}
+
+impl TryFrom<u8> for TerrainType {
+    type Error = Error;
+
+    fn try_from(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(TerrainType::Grassland),
+            1 => Ok(TerrainType::Iron),
+            2 => Ok(TerrainType::Forest),
+            3 => Ok(TerrainType::Hills),
+            4 => Ok(TerrainType::Mountains),
+            5 => Ok(TerrainType::Stone),
+            6 => Ok(TerrainType::Plains),
+            7 => Ok(TerrainType::Pasture),
+            8 => Ok(TerrainType::Desert),
+            9 => Ok(TerrainType::Water),
+            _ => err!(GameError::InvalidTerrain),
+        }
+    }
+}
+
+impl TerrainType {
+    /// returns `(food, production, gold)` yielded by the tile to the city founded on it
+    pub fn yields(&self) -> (u32, u32, u32) {
+        match self {
+            TerrainType::Grassland => (2, 0, 0),
+            TerrainType::Iron => (0, 1, 0),
+            TerrainType::Forest => (1, 1, 0),
+            TerrainType::Hills => (0, 2, 0),
+            TerrainType::Mountains => (0, 0, 0),
+            TerrainType::Stone => (0, 1, 0),
+            TerrainType::Plains => (1, 1, 0),
+            TerrainType::Pasture => (1, 0, 1),
+            TerrainType::Desert => (0, 0, 0),
+            TerrainType::Water => (1, 0, 1),
+        }
+    }
+
+    /// Movement points needed to enter the tile, `None` if the tile is impassable.
+    pub fn movement_cost(&self) -> Option<u8> {
+        match self {
+            TerrainType::Mountains | TerrainType::Water => None,
+            TerrainType::Iron | TerrainType::Forest | TerrainType::Hills | TerrainType::Stone => {
+                Some(2)
+            }
+            TerrainType::Grassland
+            | TerrainType::Plains
+            | TerrainType::Pasture
+            | TerrainType::Desert => Some(1),
+        }
+    }
+
+    /// Combat strength added to a unit defending on the tile.
+    pub fn defense_bonus(&self) -> u8 {
+        match self {
+            TerrainType::Hills => 3,
+            TerrainType::Forest | TerrainType::Iron | TerrainType::Stone => 2,
+            _ => 0,
+        }
+    }
+
+    /// Cities can be founded on any passable terrain.
+    pub fn is_buildable(&self) -> bool {
+        self.movement_cost().is_some()
+    }
+
+    /// Improvement that a Builder can construct on the tile.
+    pub fn improvement(&self) -> Option<TileType> {
+        match self {
+            TerrainType::Iron => Some(TileType::IronMine),
+            TerrainType::Forest => Some(TileType::LumberMill),
+            TerrainType::Stone => Some(TileType::StoneQuarry),
+            TerrainType::Plains => Some(TileType::Farm),
+            TerrainType::Pasture => Some(TileType::Pasture),
+            _ => None,
+        }
+    }
 }
 
 #[account]
@@ -34,6 +161,10 @@ pub struct Player {
     pub research_accumulated_points: u32,
     pub next_city_id: u32,
     pub next_unit_id: u32,
+    /// Bitset of the map tiles discovered by this civilization, one bit per tile
+    pub discovered_tiles: [u8; 50],
+    /// Combat or end of turn waiting for the hash of a later slot
+    pub pending_commitment: Option<Commitment>,
 }
 
 #[account]
@@ -46,15 +177,64 @@ pub struct Npc {
     pub next_unit_id: u32,
 }
 
+impl Game {
+    pub fn has_player(&self, player: &Pubkey) -> bool {
+        self.players.contains(player)
+    }
+
+    pub fn is_current_player(&self, player: &Pubkey) -> bool {
+        self.players.get(self.current_player_index as usize) == Some(player)
+    }
+
+    /// Checks whether the current player is the last one to end their turn in the round.
+    pub fn is_last_in_round(&self) -> bool {
+        self.current_player_index as usize + 1 >= self.players.len()
+    }
+
+    /// Passes the turn to the next player in the turn order at the time `now`.
+    /// Returns `true` when every player has ended their turn and a new round starts.
+    pub fn advance_turn(&mut self, now: i64) -> bool {
+        self.turn_started_at = now;
+        let next_index = self.current_player_index as usize + 1;
+        if next_index >= self.players.len() {
+            self.current_player_index = 0;
+            true
+        } else {
+            self.current_player_index = next_index as u8;
+            false
+        }
+    }
+
+    /// Removes the player from the turn order, keeping the turn with the same player when possible.
+    pub fn remove_player(&mut self, player: &Pubkey) {
+        if let Some(index) = self.players.iter().position(|p| p == player) {
+            self.players.remove(index);
+            if index < self.current_player_index as usize {
+                self.current_player_index -= 1;
+            }
+            if self.current_player_index as usize >= self.players.len() {
+                self.current_player_index = 0;
+            }
+        }
+        self.relations.retain(|r| !r.involves(player));
+    }
+}
+
 impl Player {
-    fn count_barracks_buildings(cities: &[City]) -> u32 {
+    fn count_buildings(cities: &[City], building_type: BuildingType) -> u32 {
         cities
             .iter()
             .flat_map(|city| &city.buildings)
-            .filter(|&building| matches!(building, BuildingType::Barracks))
+            .filter(|&building| *building == building_type)
             .count() as u32
     }
 
+    /// Max amount of food in the player's stockpile, every Granary adds more space.
+    pub fn food_storage_capacity(&self) -> u32 {
+        FOOD_STORAGE_CAPACITY
+            + Self::count_buildings(&self.cities, BuildingType::Granary) * GRANARY_FOOD_CAPACITY
+    }
+
     pub fn update_resources(
         &mut self,
         gold: i32,
@@ -62,6 +242,7 @@ impl Player {
         stone: u32,
         iron: u32,
         horses: u32,
+        food: u32,
     ) -> Result<()> {
         self.resources.gold = self.resources.gold.checked_add(gold).unwrap_or({
             if gold > 0 {
@@ -71,7 +252,7 @@ impl Player {
             }
         });
 
-        let barracks_count = Self::count_barracks_buildings(&self.cities);
+        let barracks_count = Self::count_buildings(&self.cities, BuildingType::Barracks);
         // Each barracks adds +10 to the max storage capacity
         let max_storage_capacity = STORAGE_CAPACITY as u32 + (barracks_count * 10);
 
@@ -83,6 +264,7 @@ impl Player {
         self.resources.stone = add_resource(self.resources.stone, stone, max_storage_capacity);
         self.resources.iron = add_resource(self.resources.iron, iron, max_storage_capacity);
         self.resources.horses = add_resource(self.resources.horses, horses, max_storage_capacity);
+        self.resources.food = add_resource(self.resources.food, food, self.food_storage_capacity());
 
         Ok(())
     }
@@ -1,11 +1,17 @@
 pub mod city_buildings;
+pub mod diplomacy;
 pub mod game_state;
+pub mod randomness;
 pub mod resources_economy;
 pub mod science;
+pub mod session;
 pub mod units_combat;
 
 pub use city_buildings::*;
+pub use diplomacy::*;
 pub use game_state::*;
+pub use randomness::*;
 pub use resources_economy::*;
 pub use science::*;
+pub use session::*;
 pub use units_combat::*;
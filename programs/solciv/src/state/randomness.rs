@@ -0,0 +1,40 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+
+/// Hash chain stored per game. Before an action draws random values, the seed is mixed with the hash
+/// of the first slot after the one the action was committed in, and every draw hashes the seed with a fresh nonce.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Randomness {
+    pub seed: [u8; 32],
+    pub nonce: u64,
+}
+
+/// Action committed by a player, resolved with the hash of a slot after `slot`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq)]
+pub struct Commitment {
+    /// hash of the action name and its arguments
+    pub action: [u8; 32],
+    pub slot: u64,
+}
+
+impl Randomness {
+    pub fn new(entropy: &[&[u8]]) -> Self {
+        Self {
+            seed: hashv(entropy).to_bytes(),
+            nonce: 0,
+        }
+    }
+
+    pub fn reseed(&mut self, entropy: &[u8]) {
+        self.seed = hashv(&[&self.seed, entropy]).to_bytes();
+    }
+
+    /// Returns a fresh value in the range `[0, max)`.
+    pub fn next_below(&mut self, max: u64) -> u64 {
+        self.nonce = self.nonce.wrapping_add(1);
+        let hash = hashv(&[&self.seed, &self.nonce.to_le_bytes()]).to_bytes();
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(value) % max
+    }
+}
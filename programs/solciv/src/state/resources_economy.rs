@@ -8,6 +8,7 @@ pub struct Resources {
     pub iron: u32,
     pub gems: u32,
     pub horses: u32,
+    pub food: u32,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
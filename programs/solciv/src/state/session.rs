@@ -0,0 +1,36 @@
+use crate::state::Player;
+use anchor_lang::prelude::*;
+
+/// Temporary key authorized by the player's main wallet to sign in-game actions
+/// (moving, attacking, queueing production and ending turns) for a limited number of turns.
+#[account]
+pub struct SessionToken {
+    pub player: Pubkey,
+    pub game: Pubkey,
+    pub session_key: Pubkey,
+    pub expires_at_turn: u32,
+}
+
+impl SessionToken {
+    pub fn is_valid(&self, player_account: &Player, signer: &Pubkey, turn: u32) -> bool {
+        self.player == player_account.player
+            && self.game == player_account.game
+            && self.session_key == *signer
+            && turn < self.expires_at_turn
+    }
+}
+
+impl Player {
+    /// The main wallet is always authorized, a session key only with a valid session token.
+    pub fn is_authorized(
+        &self,
+        signer: &Pubkey,
+        session_token: Option<&SessionToken>,
+        turn: u32,
+    ) -> bool {
+        if *signer == self.player {
+            return true;
+        }
+        session_token.map_or(false, |token| token.is_valid(self, signer, turn))
+    }
+}
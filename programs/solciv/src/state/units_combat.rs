@@ -1,6 +1,6 @@
 use crate::consts::*;
 use crate::errors::*;
-use crate::state::{City, TechnologyType};
+use crate::state::{City, TechnologyType, Terrain};
 use crate::utils::*;
 use anchor_lang::prelude::*;
 
@@ -24,6 +24,103 @@ pub struct Unit {
     pub maintenance_cost: i32,
     pub is_ranged: bool,
     pub is_alive: bool,
+    pub stance: UnitStance,
+    pub fortify_turns: u8,
+    /// promotion chosen on every level up, in the order of levels
+    pub promotions: [Option<Promotion>; 3],
+}
+
+/// Standing order of a unit, kept until the unit moves.
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq)]
+pub enum UnitStance {
+    None,
+    /// unit digs in and gets stronger in defense every turn it stays
+    Fortified,
+    /// unit attacks barbarians that end their turn next to it
+    Alert,
+    /// unit is skipped until woken up
+    Sleep,
+}
+
+/// Bonus chosen by a unit when it levels up, the choice depends on the unit class.
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq)]
+pub enum Promotion {
+    /// +2 combat strength, can be taken on every level
+    Strength,
+    /// +1 movement point
+    Mobility,
+    /// +3 combat strength in defense on hills, forests, iron and stone tiles
+    Cover,
+    /// heals 30 HP after killing a unit
+    Medic,
+    /// +1 attack range
+    Range,
+}
+
+/// Modifiers of a unit attack, read from the tiles of both units.
+#[derive(Clone, Copy, Default)]
+pub struct CombatModifiers {
+    /// defender stays in its own city with a wall, given damage is halved
+    pub behind_the_wall: bool,
+    /// combat strength added to the defender by the terrain of its tile
+    pub terrain_defense: u8,
+    /// attacker has to cross a river to reach the defender
+    pub river_crossing: bool,
+}
+
+impl CombatModifiers {
+    pub fn new(map: &[Terrain], attacker: &Unit, defender: &Unit, behind_the_wall: bool) -> Self {
+        let attacker_tile = &map[attacker.y as usize * MAP_BOUND as usize + attacker.x as usize];
+        let defender_tile = &map[defender.y as usize * MAP_BOUND as usize + defender.x as usize];
+        let mut terrain_defense = defender_tile.terrain.defense_bonus();
+        if terrain_defense > 0 && defender.has_promotion(Promotion::Cover) {
+            terrain_defense = terrain_defense.saturating_add(PROMOTION_COVER_BONUS);
+        }
+        Self {
+            behind_the_wall,
+            terrain_defense,
+            river_crossing: defender_tile.river && !attacker_tile.river,
+        }
+    }
+}
+
+/// Target of a previewed attack: a unit or a city id of the defending civilization.
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq)]
+pub enum AttackTarget {
+    Unit(u32),
+    City(u32),
+}
+
+/// Expected outcome of an attack over the whole range of the random factor.
+/// Damage done to a city includes the damage absorbed by its wall.
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, Default)]
+pub struct AttackPreview {
+    pub min_given_damage: u32,
+    pub max_given_damage: u32,
+    pub min_taken_damage: u32,
+    pub max_taken_damage: u32,
+    pub attacker_may_die: bool,
+    pub defender_may_die: bool,
+}
+
+impl AttackPreview {
+    /// Adds the outcome of one simulated attack, `attacker` is the attacker after the fight.
+    /// A unit healed by the Medic promotion counts as taking no damage.
+    pub fn record(
+        &mut self,
+        given_damage: u32,
+        attacker_health_before: u8,
+        attacker: &Unit,
+        defender_died: bool,
+    ) {
+        let taken_damage = attacker_health_before.saturating_sub(attacker.health) as u32;
+        self.min_given_damage = self.min_given_damage.min(given_damage);
+        self.max_given_damage = self.max_given_damage.max(given_damage);
+        self.min_taken_damage = self.min_taken_damage.min(taken_damage);
+        self.max_taken_damage = self.max_taken_damage.max(taken_damage);
+        self.attacker_may_die |= !attacker.is_alive;
+        self.defender_may_die |= defender_died;
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq)]
@@ -38,6 +135,62 @@ pub enum UnitType {
     Rifleman,
     Tank,
     Horseman,
+    Spearman,
+    Pikeman,
+    Catapult,
+    Trebuchet,
+    Cannon,
+    Artillery,
+}
+
+/// Combat role of a unit type, used for the counter bonuses between units.
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq)]
+pub enum UnitClass {
+    Civilian,
+    Melee,
+    Ranged,
+    Cavalry,
+    AntiCavalry,
+    Siege,
+    Armor,
+}
+
+impl UnitClass {
+    /// Combat strength bonus of this class against units of the `other` class.
+    pub fn bonus_against(&self, other: UnitClass) -> u8 {
+        match (self, other) {
+            (UnitClass::AntiCavalry, UnitClass::Cavalry) => 10,
+            (UnitClass::AntiCavalry, UnitClass::Armor) => 5,
+            (UnitClass::Cavalry, UnitClass::Ranged) => 5,
+            (UnitClass::Cavalry, UnitClass::Siege) => 10,
+            (UnitClass::Melee, UnitClass::AntiCavalry) => 5,
+            (UnitClass::Armor, UnitClass::Melee) => 5,
+            _ => 0,
+        }
+    }
+
+    /// Combat strength lost in fights against other units.
+    pub fn unit_combat_penalty(&self) -> u8 {
+        match self {
+            UnitClass::Siege => SIEGE_UNIT_PENALTY,
+            _ => 0,
+        }
+    }
+
+    /// Promotions units of this class can choose from on level up.
+    pub fn promotions(&self) -> &'static [Promotion] {
+        match self {
+            UnitClass::Civilian => &[],
+            UnitClass::Melee | UnitClass::AntiCavalry => {
+                &[Promotion::Strength, Promotion::Cover, Promotion::Medic]
+            }
+            UnitClass::Cavalry | UnitClass::Armor => {
+                &[Promotion::Strength, Promotion::Mobility, Promotion::Medic]
+            }
+            UnitClass::Ranged => &[Promotion::Strength, Promotion::Range, Promotion::Cover],
+            UnitClass::Siege => &[Promotion::Strength, Promotion::Range, Promotion::Mobility],
+        }
+    }
 }
 
 impl Unit {
@@ -82,6 +235,9 @@ impl Unit {
             maintenance_cost,
             is_ranged,
             is_alive: true,
+            stance: UnitStance::None,
+            fortify_turns: 0,
+            promotions: [None; 3],
         }
     }
 
@@ -105,6 +261,12 @@ impl Unit {
             UnitType::Archer => (true, 100, 10, 2, 0, 20, 200, 0, 1, 0, 0),
             UnitType::Swordsman => (false, 100, 14, 2, 0, 30, 240, 10, 1, 0, 0),
             UnitType::Horseman => (false, 100, 14, 3, 0, 30, 280, 10, 2, 0, 0),
+            UnitType::Spearman => (false, 100, 11, 2, 0, 25, 220, 0, 1, 0, 0),
+            UnitType::Pikeman => (false, 100, 20, 2, 0, 40, 300, 10, 2, 0, 0),
+            UnitType::Catapult => (true, 100, 16, 2, 0, 35, 260, 0, 2, 0, 0),
+            UnitType::Trebuchet => (true, 100, 26, 2, 0, 45, 320, 0, 2, 0, 0),
+            UnitType::Cannon => (true, 100, 36, 2, 0, 55, 400, 0, 3, 0, 0),
+            UnitType::Artillery => (true, 100, 46, 2, 0, 70, 480, 0, 4, 0, 0),
             UnitType::Crossbowman => (true, 100, 24, 2, 0, 40, 240, 0, 2, 0, 0),
             UnitType::Musketman => (true, 100, 32, 2, 0, 50, 360, 0, 2, 0, 0),
             UnitType::Rifleman => (true, 100, 40, 3, 0, 60, 420, 0, 4, 0, 0),
@@ -136,12 +298,168 @@ impl Unit {
         Unit::get_base_stats(unit_type).9
     }
 
+    /// Returns the max distance the unit can attack from, ranged units can shoot over one tile.
+    pub fn get_attack_range(&self) -> u8 {
+        let range = if self.is_ranged {
+            RANGED_ATTACK_RANGE
+        } else {
+            MELEE_ATTACK_RANGE
+        };
+        if self.has_promotion(Promotion::Range) {
+            range + 1
+        } else {
+            range
+        }
+    }
+
+    /// Returns the movement points the unit gets every turn.
+    pub fn get_movement_range(&self) -> u8 {
+        let movement_range = Unit::get_base_movement_range(self.unit_type);
+        if self.has_promotion(Promotion::Mobility) {
+            movement_range + 1
+        } else {
+            movement_range
+        }
+    }
+
+    pub fn has_promotion(&self, promotion: Promotion) -> bool {
+        self.promotions.contains(&Some(promotion))
+    }
+
+    /// Levels up the unit with the chosen promotion, the unit is healed and cannot act until the next turn.
+    pub fn promote(&mut self, promotion: Promotion) -> Result<()> {
+        if !self.unit_type.class().promotions().contains(&promotion) {
+            return err!(UnitError::InvalidPromotion);
+        }
+        // only the strength bonus can be taken more than once
+        if promotion != Promotion::Strength && self.has_promotion(promotion) {
+            return err!(UnitError::InvalidPromotion);
+        }
+        let slot = self
+            .promotions
+            .get_mut(self.level as usize)
+            .ok_or(UnitError::MaxLevelReached)?;
+        *slot = Some(promotion);
+
+        if promotion == Promotion::Strength {
+            self.attack = self.attack.saturating_add(PROMOTION_STRENGTH_BONUS);
+        }
+        self.level += 1;
+        self.health = std::cmp::min(self.health.saturating_add(LEVEL_UP_HEAL), 100);
+        self.movement_range = 0;
+
+        Ok(())
+    }
+
+    /// Turns the unit into the `unit_type`, keeping its health, experience, level and promotions.
+    pub fn upgrade_type(&mut self, unit_type: UnitType) {
+        let upgraded = Unit::new(
+            self.unit_id,
+            self.player,
+            self.game,
+            unit_type,
+            self.x,
+            self.y,
+        );
+        let strength_promotions = self
+            .promotions
+            .iter()
+            .filter(|p| **p == Some(Promotion::Strength))
+            .count() as u8;
+        *self = Unit {
+            attack: upgraded
+                .attack
+                .saturating_add(strength_promotions * PROMOTION_STRENGTH_BONUS),
+            health: self.health,
+            level: self.level,
+            experience: self.experience,
+            promotions: self.promotions,
+            // the upgrade takes the whole turn of the unit
+            movement_range: 0,
+            ..upgraded
+        };
+    }
+
+    fn heal_after_kill(&mut self) {
+        if self.is_alive && self.has_promotion(Promotion::Medic) {
+            self.health = std::cmp::min(self.health.saturating_add(PROMOTION_KILL_HEAL), 100);
+        }
+    }
+
+    /// Combat strength added to the defense of a fortified unit, stacking with every turn fortified.
+    pub fn fortify_bonus(&self) -> u8 {
+        if self.stance == UnitStance::Fortified {
+            self.fortify_turns.saturating_mul(FORTIFY_DEFENSE_BONUS)
+        } else {
+            0
+        }
+    }
+
+    pub fn set_stance(&mut self, stance: UnitStance) -> Result<()> {
+        // only military units can fortify or stay on alert
+        if matches!(stance, UnitStance::Fortified | UnitStance::Alert) && !self.can_attack() {
+            return err!(UnitError::InvalidUnitType);
+        }
+        self.stance = stance;
+        self.fortify_turns = 0;
+        Ok(())
+    }
+
+    pub fn clear_stance(&mut self) {
+        self.stance = UnitStance::None;
+        self.fortify_turns = 0;
+    }
+
+    pub fn is_civilian(&self) -> bool {
+        self.unit_type.class() == UnitClass::Civilian
+    }
+
+    /// One civilian and one military unit can stay on the same tile.
+    pub fn can_share_tile(&self, other: &Unit) -> bool {
+        self.is_civilian() != other.is_civilian()
+    }
+
+    /// Civilian unit taken away from its owner by an attack.
+    pub fn is_captured(&self) -> bool {
+        !self.is_alive && self.is_civilian()
+    }
+
+    /// Returns the captured unit as a new unit of the `player`, it cannot move until the next turn.
+    pub fn captured_by(&self, unit_id: u32, player: Pubkey) -> Unit {
+        Unit {
+            unit_id,
+            player,
+            is_alive: true,
+            movement_range: 0,
+            stance: UnitStance::None,
+            fortify_turns: 0,
+            ..*self
+        }
+    }
+
+    /// Returns the iron or horses given back when the unit is disbanded outside a city.
+    /// Only the units recruited with these resources return a part of them,
+    /// the starting and captured units were never paid for.
+    pub fn disband_refund(&self) -> u32 {
+        match self.unit_type {
+            UnitType::Swordsman | UnitType::Pikeman | UnitType::Horseman => {
+                self.base_resource_cost * DISBAND_REFUND_PERCENT / 100
+            }
+            _ => 0,
+        }
+    }
+
+    /// Only melee units can move into a defeated city and capture it.
+    pub fn can_capture_city(&self) -> bool {
+        self.is_alive && !self.is_ranged && self.can_attack()
+    }
+
     fn can_attack(&self) -> bool {
         // only 2 units cannot attack: Settler and Builder
         !matches!(self.unit_type, UnitType::Settler | UnitType::Builder)
     }
 
-    fn apply_damage(&mut self, damage: u8) {
+    pub fn apply_damage(&mut self, damage: u8) {
         if damage >= self.health {
             self.is_alive = false;
             self.health = 0;
@@ -162,49 +480,67 @@ impl Unit {
     pub fn attack_unit(
         &mut self,
         defender: &mut Unit,
-        defender_behind_the_wall: Option<bool>,
+        modifiers: CombatModifiers,
+        random_factor: u8,
     ) -> Result<()> {
         // Check if the attacker is alive and of attacking type
         if !self.is_alive || !self.can_attack() {
             return err!(UnitError::InvalidAttack);
         }
 
-        // Check if defender is of neutral type (Settler or Builder)
-        if matches!(defender.unit_type, UnitType::Settler | UnitType::Builder) {
+        // Civilian defender (Settler or Builder) is captured, the captor takes it with `captured_by`
+        if defender.is_civilian() {
             defender.is_alive = false;
-            defender.health = 0;
-            msg!("Defender is dead");
+            msg!("Defender is captured");
             // set movement range to 0 so that the attacker cannot move or attack anymore
             self.movement_range = 0;
             return Ok(());
         }
         // Calculate given damage and taken damage by a formula:
         // damage = 30 * e^((difference between combat strengths) / 25) * random_factor
-        let e: f32 = std::f32::consts::E;
-        // get the unix timestamp modulo 10 to get a number in the range [0, 9]
-        let clock = Clock::get()?;
-        let random_factor = clock.unix_timestamp % 10;
-
+        // all math is done in fixed point, so clients can reproduce the result exactly
+        // random_factor is in the range [0, 9]
         // map this to a range of [0.9, ~1.1007]
-        let multiplier: f32 = 0.9 + ((random_factor as f32) * 0.0223);
+        let multiplier = damage_multiplier(random_factor);
         // @todo: do we really need the multiplier for the taken damage?
-        let taken_damage_multiplier: f32 = 1.0 / multiplier;
+        let taken_damage_multiplier = inverse_multiplier(multiplier);
+        // terrain makes the defender stronger, crossing a river makes the attacker weaker
+        let attacker_strength = if modifiers.river_crossing {
+            self.attack.saturating_sub(RIVER_CROSSING_PENALTY)
+        } else {
+            self.attack
+        };
+        // both sides get their class bonus against each other
+        let attacker_class = self.unit_type.class();
+        let defender_class = defender.unit_type.class();
+        let attacker_strength = attacker_strength
+            .saturating_add(attacker_class.bonus_against(defender_class))
+            .saturating_sub(attacker_class.unit_combat_penalty());
+        let defender_strength = defender
+            .attack
+            .saturating_add(modifiers.terrain_defense)
+            .saturating_add(defender.fortify_bonus())
+            .saturating_add(defender_class.bonus_against(attacker_class))
+            .saturating_sub(defender_class.unit_combat_penalty());
+        let strength_diff = attacker_strength as i32 - defender_strength as i32;
         let mut given_damage_raw =
-            30.0 * e.powf((self.attack as f32 - defender.attack as f32) / 25.0) * multiplier
-                - 10.0 * (100.0 - self.health as f32) / 100.0;
+            scaled_damage(30, strength_diff, multiplier) - wounded_penalty(self.health);
 
-        if defender_behind_the_wall.is_some() {
+        if modifiers.behind_the_wall {
             // decrease given damage by 2 if defender unit behind the wall
-            given_damage_raw /= 2.0;
+            given_damage_raw /= 2;
         }
 
-        let taken_damage_raw = 30.0
-            * e.powf((defender.attack as f32 - self.attack as f32) / 25.0)
-            * taken_damage_multiplier
-            - 10.0 * (100.0 - defender.health as f32) / 100.0;
+        let taken_damage_raw = scaled_damage(30, -strength_diff, taken_damage_multiplier)
+            - wounded_penalty(defender.health);
 
-        let given_damage = (given_damage_raw.max(0.0).min(255.0)) as u8;
-        let taken_damage = (taken_damage_raw.max(0.0).min(255.0)) as u8;
+        let given_damage = fixed_to_damage(given_damage_raw);
+        // ranged units don't receive counter-damage
+        let taken_damage = if self.is_ranged {
+            0
+        } else {
+            fixed_to_damage(taken_damage_raw)
+        };
 
         // Apply damage to defender
         defender.apply_damage(given_damage);
@@ -234,13 +570,21 @@ impl Unit {
             defender.update_experience(!self.is_alive);
         }
 
+        // the winner of a deadly fight may heal
+        if !defender.is_alive {
+            self.heal_after_kill();
+        }
+        if !self.is_alive {
+            defender.heal_after_kill();
+        }
+
         // After the attack unit cannot move or attack anymore
         self.movement_range = 0;
 
         Ok(())
     }
 
-    pub fn attack_city(&mut self, city: &mut City) -> Result<()> {
+    pub fn attack_city(&mut self, city: &mut City, random_factor: u8) -> Result<()> {
         if !self.is_alive || !self.can_attack() {
             return err!(UnitError::InvalidAttack);
         }
@@ -251,26 +595,37 @@ impl Unit {
             city_defense = city.attack;
         }
 
+        // siege units are stronger against cities and break walls at full damage
+        let is_siege = self.unit_type.class() == UnitClass::Siege;
+        let attack = if is_siege {
+            self.attack.saturating_add(SIEGE_CITY_BONUS)
+        } else {
+            self.attack
+        };
+
         // Similar damage calculations as attack_unit
-        let e: f32 = std::f32::consts::E;
-        let clock = Clock::get()?;
-        let random_factor = clock.unix_timestamp % 10;
-        let multiplier: f32 = 0.9 + ((random_factor as f32) * 0.0223);
-        let taken_damage_multiplier: f32 = 1.0 / multiplier;
-        let given_damage =
-            (15.0 * e.powf((self.attack as f32 - city_defense as f32) / 25.0) * multiplier) as u8;
-        let taken_damage = (15.0
-            * e.powf((city_defense as f32 - self.attack as f32) / 25.0)
-            * taken_damage_multiplier) as u8;
+        let multiplier = damage_multiplier(random_factor);
+        let taken_damage_multiplier = inverse_multiplier(multiplier);
+        let strength_diff = attack as i32 - city_defense as i32;
+        let given_damage = fixed_to_damage(scaled_damage(15, strength_diff, multiplier));
+        let taken_damage = if self.is_ranged {
+            0
+        } else {
+            fixed_to_damage(scaled_damage(15, -strength_diff, taken_damage_multiplier))
+        };
 
         if city.wall_health > 0 {
-            // damage for wall decreased by 2
-            let given_wall_damage = given_damage / 2;
+            // damage for wall decreased by 2, unless the attacker is a siege unit
+            let given_wall_damage = if is_siege {
+                given_damage
+            } else {
+                given_damage / 2
+            };
             // handle damage for city wall
             if city.wall_health < given_wall_damage as u32 {
                 let city_damage = u32::from(given_wall_damage) - city.wall_health;
                 city.wall_health = 0;
-                city.health -= city_damage;
+                city.health = city.health.saturating_sub(city_damage);
                 msg!("City HP after attack: {}", city.health);
                 msg!("City Wall destroyed");
             } else {
@@ -308,6 +663,22 @@ impl Unit {
 }
 
 impl UnitType {
+    pub fn class(&self) -> UnitClass {
+        match self {
+            UnitType::Settler | UnitType::Builder => UnitClass::Civilian,
+            UnitType::Warrior | UnitType::Swordsman => UnitClass::Melee,
+            UnitType::Archer | UnitType::Crossbowman | UnitType::Musketman | UnitType::Rifleman => {
+                UnitClass::Ranged
+            }
+            UnitType::Horseman => UnitClass::Cavalry,
+            UnitType::Spearman | UnitType::Pikeman => UnitClass::AntiCavalry,
+            UnitType::Tank => UnitClass::Armor,
+            UnitType::Catapult | UnitType::Trebuchet | UnitType::Cannon | UnitType::Artillery => {
+                UnitClass::Siege
+            }
+        }
+    }
+
     pub fn can_recruit(&self, researched_technologies: &[TechnologyType]) -> bool {
         match self {
             UnitType::Settler | UnitType::Builder | UnitType::Warrior => true, // No tech required
@@ -322,6 +693,91 @@ impl UnitType {
             UnitType::Musketman => researched_technologies.contains(&TechnologyType::Gunpowder),
             UnitType::Rifleman => researched_technologies.contains(&TechnologyType::Ballistics),
             UnitType::Tank => researched_technologies.contains(&TechnologyType::TanksAndArmor),
+            UnitType::Spearman => {
+                researched_technologies.contains(&TechnologyType::AnimalHusbandry)
+            }
+            UnitType::Pikeman => researched_technologies.contains(&TechnologyType::MedievalWarfare),
+            UnitType::Catapult => researched_technologies.contains(&TechnologyType::IronWorking),
+            UnitType::Trebuchet => {
+                researched_technologies.contains(&TechnologyType::MedievalWarfare)
+            }
+            UnitType::Cannon => researched_technologies.contains(&TechnologyType::Gunpowder),
+            UnitType::Artillery => researched_technologies.contains(&TechnologyType::Ballistics),
+        }
+    }
+
+    /// Returns the newer unit type this unit can be upgraded to.
+    pub fn successor(&self) -> Option<UnitType> {
+        match self {
+            UnitType::Warrior => Some(UnitType::Swordsman),
+            UnitType::Swordsman => Some(UnitType::Musketman),
+            UnitType::Archer => Some(UnitType::Crossbowman),
+            UnitType::Crossbowman => Some(UnitType::Rifleman),
+            UnitType::Musketman => Some(UnitType::Rifleman),
+            UnitType::Spearman => Some(UnitType::Pikeman),
+            UnitType::Horseman => Some(UnitType::Tank),
+            UnitType::Catapult => Some(UnitType::Trebuchet),
+            UnitType::Trebuchet => Some(UnitType::Cannon),
+            UnitType::Cannon => Some(UnitType::Artillery),
+            _ => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_of_a_kill_by_a_medic_does_not_underflow() {
+        let mut attacker = Unit::new(
+            0,
+            Pubkey::default(),
+            Pubkey::default(),
+            UnitType::Warrior,
+            0,
+            0,
+        );
+        attacker.health = 50;
+        attacker.level = 1;
+        attacker.promotions[0] = Some(Promotion::Medic);
+        let mut defender = Unit::new(
+            1,
+            Pubkey::default(),
+            Pubkey::default(),
+            UnitType::Warrior,
+            1,
+            0,
+        );
+        defender.health = 1;
+
+        let mut preview = AttackPreview {
+            min_given_damage: u32::MAX,
+            min_taken_damage: u32::MAX,
+            ..Default::default()
+        };
+        let mut attacker_copy = attacker;
+        attacker_copy
+            .attack_unit(&mut defender, CombatModifiers::default(), 0)
+            .unwrap();
+        assert!(attacker_copy.health > attacker.health);
+
+        preview.record(1, attacker.health, &attacker_copy, !defender.is_alive);
+        assert_eq!(preview.max_taken_damage, 0);
+        assert!(preview.defender_may_die);
+    }
+
+    #[test]
+    fn only_units_recruited_with_resources_refund_them() {
+        let unit = |unit_type| Unit::new(0, Pubkey::default(), Pubkey::default(), unit_type, 0, 0);
+
+        assert_eq!(unit(UnitType::Swordsman).disband_refund(), 5);
+        assert_eq!(unit(UnitType::Pikeman).disband_refund(), 5);
+        assert_eq!(unit(UnitType::Horseman).disband_refund(), 5);
+        // the starting units and captured civilians cost no iron or horses
+        assert_eq!(unit(UnitType::Settler).disband_refund(), 0);
+        assert_eq!(unit(UnitType::Builder).disband_refund(), 0);
+        assert_eq!(unit(UnitType::Warrior).disband_refund(), 0);
+        assert_eq!(unit(UnitType::Tank).disband_refund(), 0);
+    }
+}
@@ -1,4 +1,9 @@
 use crate::consts::*;
+use crate::errors::GameError;
+use crate::state::{Game, Player, Terrain, TileCoordinate, Unit};
+use anchor_lang::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
 pub fn get_new_exp(current_level: u8, current_exp: u8, exp_amount: u8) -> u8 {
     if current_level as usize >= EXP_THRESHOLDS.len() {
@@ -16,3 +21,321 @@ pub fn get_new_exp(current_level: u8, current_exp: u8, exp_amount: u8) -> u8 {
         new_exp
     }
 }
+
+/// Fixed-point `e^(strength_diff / 25)`, built from lookup tables so it is exact on every platform.
+pub fn exp_strength_diff(strength_diff: i32) -> u128 {
+    let strength_diff = strength_diff.clamp(-MAX_STRENGTH_DIFF, MAX_STRENGTH_DIFF);
+    let whole = strength_diff.div_euclid(25);
+    let fraction = EXP_FRACTION_TABLE[strength_diff.rem_euclid(25) as usize];
+    if whole >= 0 {
+        EXP_WHOLE_TABLE[whole as usize] * fraction / FIXED_POINT_SCALE
+    } else {
+        fraction * FIXED_POINT_SCALE / EXP_WHOLE_TABLE[whole.unsigned_abs() as usize]
+    }
+}
+
+/// Maps a random factor in the range [0, 9] to a fixed-point multiplier in the range [0.9, ~1.1007].
+pub fn damage_multiplier(random_factor: u8) -> u128 {
+    DAMAGE_MULTIPLIER_BASE + random_factor as u128 * DAMAGE_MULTIPLIER_STEP
+}
+
+/// Fixed-point `1 / multiplier`.
+pub fn inverse_multiplier(multiplier: u128) -> u128 {
+    FIXED_POINT_SCALE * FIXED_POINT_SCALE / multiplier
+}
+
+/// Fixed-point `base_damage * e^(strength_diff / 25) * multiplier`.
+pub fn scaled_damage(base_damage: u8, strength_diff: i32, multiplier: u128) -> i64 {
+    (base_damage as u128 * exp_strength_diff(strength_diff) * multiplier / FIXED_POINT_SCALE) as i64
+}
+
+/// Fixed-point damage penalty of a wounded unit: up to 10 damage less at 0 HP.
+pub fn wounded_penalty(health: u8) -> i64 {
+    (100 - health.min(100) as i64) * FIXED_POINT_SCALE as i64 / 10
+}
+
+/// Rounds fixed-point damage down and clamps it to [0, 255].
+pub fn fixed_to_damage(damage: i64) -> u8 {
+    (damage.clamp(0, 255 * FIXED_POINT_SCALE as i64) / FIXED_POINT_SCALE as i64) as u8
+}
+
+/// Returns the hash of the first slot after `slot` stored in the SlotHashes sysvar,
+/// or `None` when that slot is already too old to be stored.
+#[cfg(not(feature = "stub-randomness"))]
+fn slot_hash_after(slot_hashes: &AccountInfo, slot: u64) -> Result<Option<[u8; 32]>> {
+    let data = slot_hashes.try_borrow_data()?;
+    // layout: u64 number of entries, followed by (u64 slot, [u8; 32] hash) entries, newest first
+    if data.len() < 8 {
+        return err!(GameError::SlotHashesUnavailable);
+    }
+    let mut first_after = None;
+    for entry in data[8..].chunks_exact(40) {
+        let mut entry_slot = [0u8; 8];
+        entry_slot.copy_from_slice(&entry[..8]);
+        if u64::from_le_bytes(entry_slot) <= slot {
+            // the entries reach back to the committed slot, so no later slot is missing
+            return match first_after {
+                Some(hash) => Ok(Some(hash)),
+                None => err!(GameError::CommitmentNotReady),
+            };
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&entry[8..]);
+        first_after = Some(hash);
+    }
+    Ok(None)
+}
+
+/// Commits the `action` of the player, or reveals the randomness of the already committed `action`.
+///
+/// The first call stores the action with the current slot and returns `false`. A call with the same action
+/// in a later slot mixes the hash of the slot following the commitment into the game randomness and returns `true`.
+/// The outcome is therefore unknown when the action is committed, and reverting the second call cannot change it.
+/// Once the slot hash has left the SlotHashes sysvar the action is resolved with the commitment alone,
+/// so waiting for the expiry cannot roll the outcome again.
+#[cfg(not(feature = "stub-randomness"))]
+pub fn reveal_randomness(
+    game: &mut Game,
+    player: &mut Player,
+    action: &[&[u8]],
+    slot_hashes: &AccountInfo,
+) -> Result<bool> {
+    use crate::state::Commitment;
+    use anchor_lang::solana_program::hash::hashv;
+
+    let action = hashv(action).to_bytes();
+    let slot = Clock::get()?.slot;
+    match player.pending_commitment {
+        None => {
+            player.pending_commitment = Some(Commitment { action, slot });
+            msg!(
+                "Action committed in slot {}, resolve it in a later slot",
+                slot
+            );
+            Ok(false)
+        }
+        Some(commitment) if commitment.action != action => err!(GameError::CommitmentPending),
+        Some(commitment) => {
+            if slot <= commitment.slot {
+                return err!(GameError::CommitmentNotReady);
+            }
+            let seed = match slot_hash_after(slot_hashes, commitment.slot)? {
+                Some(hash) => hashv(&[&hash, &action]),
+                None => {
+                    msg!("Commitment expired, action resolved without the slot hash");
+                    hashv(&[&commitment.slot.to_le_bytes(), &action])
+                }
+            };
+            player.pending_commitment = None;
+            game.randomness.reseed(&seed.to_bytes());
+            Ok(true)
+        }
+    }
+}
+
+/// With the `stub-randomness` feature actions are resolved at once with a constant slot hash,
+/// so local tests get reproducible draws.
+#[cfg(feature = "stub-randomness")]
+pub fn reveal_randomness(
+    game: &mut Game,
+    _player: &mut Player,
+    _action: &[&[u8]],
+    _slot_hashes: &AccountInfo,
+) -> Result<bool> {
+    game.randomness.reseed(&[0u8; 32]);
+    Ok(true)
+}
+
+/// Draws the combat random factor of a committed attack, or commits the attack and returns `None`.
+pub fn draw_combat_random_factor(
+    game: &mut Game,
+    player: &mut Player,
+    action: &[&[u8]],
+    slot_hashes: &AccountInfo,
+) -> Result<Option<u8>> {
+    if reveal_randomness(game, player, action, slot_hashes)? {
+        Ok(Some(game.randomness.next_below(COMBAT_RANDOM_RANGE) as u8))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Chebyshev distance between two tiles.
+pub fn chebyshev_distance(x1: u8, y1: u8, x2: u8, y2: u8) -> u8 {
+    std::cmp::max(x1.abs_diff(x2), y1.abs_diff(y2))
+}
+
+/// Loads the accounts of the players other than `current_player`,
+/// passed as remaining accounts in the turn order.
+pub fn load_other_players<'info>(
+    game: &Account<'info, Game>,
+    current_player: &Pubkey,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<Vec<Account<'info, Player>>> {
+    let other_players: Vec<&Pubkey> = game
+        .players
+        .iter()
+        .filter(|p| *p != current_player)
+        .collect();
+    if remaining_accounts.len() != other_players.len() {
+        return err!(GameError::InvalidPlayerAccounts);
+    }
+
+    remaining_accounts
+        .iter()
+        .zip(other_players)
+        .map(|(info, player)| {
+            let account = Account::<Player>::try_from(info)?;
+            if account.game != game.key() || account.player != *player {
+                return err!(GameError::InvalidPlayerAccounts);
+            }
+            Ok(account)
+        })
+        .collect()
+}
+
+/// Marks the tiles as discovered by the player and by its allies,
+/// the accounts of the allies have to be writable.
+pub fn discover_tiles(
+    program_id: &Pubkey,
+    game: &Game,
+    player_account: &mut Player,
+    other_players: &mut [Account<Player>],
+    tiles: &[TileCoordinate],
+) -> Result<()> {
+    for tile in tiles {
+        player_account.discover_tile(tile.x, tile.y);
+    }
+
+    for ally in other_players
+        .iter_mut()
+        .filter(|p| game.are_allies(&player_account.player, &p.player))
+    {
+        if !ally.to_account_info().is_writable {
+            return err!(GameError::InvalidPlayerAccounts);
+        }
+        for tile in tiles {
+            ally.discover_tile(tile.x, tile.y);
+        }
+        ally.exit(program_id)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the index of the unit that defends the tile, a military unit covers a civilian stacked with it.
+pub fn tile_defender_index(units: &[Unit], x: u8, y: u8) -> Option<usize> {
+    units
+        .iter()
+        .enumerate()
+        .filter(|(_, u)| u.x == x && u.y == y && u.is_alive)
+        .max_by_key(|(_, u)| !u.is_civilian())
+        .map(|(idx, _)| idx)
+}
+
+/// Returns the cheapest cost of moving from `from` to `to`, or `None` if there is no path within `budget`.
+/// Units move between orthogonally adjacent tiles, pay the movement cost of every entered tile
+/// and cannot pass impassable terrain or the tiles where `is_blocked` returns true.
+pub fn find_path_cost(
+    map: &[Terrain],
+    from: (u8, u8),
+    to: (u8, u8),
+    budget: u8,
+    is_blocked: impl Fn(u8, u8) -> bool,
+) -> Option<u8> {
+    let map_index = |x: u8, y: u8| y as usize * MAP_BOUND as usize + x as usize;
+    let mut costs = [u8::MAX; MAP_BOUND as usize * MAP_BOUND as usize];
+    let mut queue = BinaryHeap::new();
+    costs[map_index(from.0, from.1)] = 0;
+    queue.push(Reverse((0u8, from.0, from.1)));
+
+    // Dijkstra's search, bounded by the movement budget
+    while let Some(Reverse((cost, x, y))) = queue.pop() {
+        if (x, y) == to {
+            return Some(cost);
+        }
+        if cost > costs[map_index(x, y)] {
+            continue;
+        }
+        let neighbours = [
+            (x.checked_sub(1), Some(y)),
+            (x.checked_add(1), Some(y)),
+            (Some(x), y.checked_sub(1)),
+            (Some(x), y.checked_add(1)),
+        ];
+        for (next_x, next_y) in neighbours {
+            let (Some(next_x), Some(next_y)) = (next_x, next_y) else {
+                continue;
+            };
+            if next_x >= MAP_BOUND || next_y >= MAP_BOUND || is_blocked(next_x, next_y) {
+                continue;
+            }
+            let Some(step_cost) = map[map_index(next_x, next_y)].terrain.movement_cost() else {
+                continue;
+            };
+            let next_cost = cost.saturating_add(step_cost);
+            if next_cost <= budget && next_cost < costs[map_index(next_x, next_y)] {
+                costs[map_index(next_x, next_y)] = next_cost;
+                queue.push(Reverse((next_cost, next_x, next_y)));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The floating point formula the fixed-point helpers replaced.
+    fn f32_damage(strength_diff: i32, random_factor: u8) -> f32 {
+        let multiplier = damage_multiplier(random_factor) as f32 / FIXED_POINT_SCALE as f32;
+        30.0 * (strength_diff as f32 / 25.0).exp() * multiplier
+    }
+
+    #[test]
+    fn fixed_point_damage_matches_f32_formula() {
+        for strength_diff in -MAX_STRENGTH_DIFF..=MAX_STRENGTH_DIFF {
+            for random_factor in 0..COMBAT_RANDOM_RANGE as u8 {
+                let fixed = scaled_damage(30, strength_diff, damage_multiplier(random_factor))
+                    as f64
+                    / FIXED_POINT_SCALE as f64;
+                let expected = f32_damage(strength_diff, random_factor) as f64;
+                assert!(
+                    (fixed - expected).abs() <= expected * 1e-5 + 1e-3,
+                    "strength diff {}, random factor {}: {} != {}",
+                    strength_diff,
+                    random_factor,
+                    fixed,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_point_damage_is_exact_at_the_edges() {
+        assert_eq!(scaled_damage(30, 30, damage_multiplier(0)), 89_643_159);
+        assert_eq!(scaled_damage(30, 30, damage_multiplier(255)), 656_038_518);
+        assert_eq!(scaled_damage(30, -30, damage_multiplier(0)), 8_132_238);
+        assert_eq!(scaled_damage(30, -30, damage_multiplier(255)), 59_514_428);
+
+        assert_eq!(
+            fixed_to_damage(scaled_damage(30, 30, damage_multiplier(0))),
+            89
+        );
+        assert_eq!(
+            fixed_to_damage(scaled_damage(30, 30, damage_multiplier(255))),
+            255
+        );
+        assert_eq!(
+            fixed_to_damage(scaled_damage(30, -30, damage_multiplier(0))),
+            8
+        );
+        assert_eq!(
+            fixed_to_damage(scaled_damage(30, -30, damage_multiplier(255))),
+            59
+        );
+    }
+}
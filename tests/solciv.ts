@@ -3,7 +3,7 @@ import { Program } from "@coral-xyz/anchor";
 import { Solciv } from "../target/types/solciv";
 import { expect } from "chai";
 import { PROGRAM_ID as TOKEN_METADATA_PROGRAM_ID } from "@metaplex-foundation/mpl-token-metadata";
-import { SYSVAR_RENT_PUBKEY, SystemProgram } from "@solana/web3.js";
+import { SYSVAR_RENT_PUBKEY, SYSVAR_SLOT_HASHES_PUBKEY, SystemProgram } from "@solana/web3.js";
 import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
 
 describe("solciv", () => {
@@ -12,8 +12,16 @@ describe("solciv", () => {
 
   const program = anchor.workspace.Solciv as Program<Solciv>;
 
+  const [registryKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("REGISTRY"), provider.publicKey.toBuffer()],
+    program.programId
+  );
+
+  // the first game of a fresh wallet has id 0, the id is encoded as u64 little-endian in the seeds
+  const gameId = new anchor.BN(0);
+
   const [gameKey] = anchor.web3.PublicKey.findProgramAddressSync(
-    [Buffer.from("GAME"), provider.publicKey.toBuffer()],
+    [Buffer.from("GAME"), provider.publicKey.toBuffer(), gameId.toArrayLike(Buffer, "le", 8)],
     program.programId
   );
 
@@ -33,8 +41,10 @@ describe("solciv", () => {
 
   async function addToProductionQueue(cityId, item) {
     const accounts = {
+      game: gameKey,
       player: provider.publicKey,
       playerAccount: playerKey,
+      sessionToken: null,
     };
     await program.methods.addToProductionQueue(cityId, item).accounts(accounts).rpc();
   }
@@ -47,6 +57,29 @@ describe("solciv", () => {
     });
   }
 
+  // attacks, city strikes and the end of turn are committed first and resolved by calling the same
+  // instruction again in a later slot, with the stub-randomness feature the first call resolves them
+  async function commitAndReveal(playerAccountKey: anchor.web3.PublicKey, send: () => Promise<string>) {
+    await send();
+    if (await waitForReveal(playerAccountKey)) {
+      await send();
+    }
+  }
+
+  // waits until the pending action of the player can be resolved, returns false if no action is pending
+  async function waitForReveal(playerAccountKey: anchor.web3.PublicKey) {
+    const account = await program.account.player.fetch(playerAccountKey);
+    if (!account.pendingCommitment) {
+      return false;
+    }
+    // the action is resolved with the hash of the slot following the commitment
+    const revealSlot = account.pendingCommitment.slot.toNumber() + 2;
+    while ((await provider.connection.getSlot()) < revealSlot) {
+      await new Promise((resolve) => setTimeout(resolve, 100));
+    }
+    return true;
+  }
+
   // Helper function to generate random coordinates during game initialization
   function getRandomCoordinates() {
     // don't spawn on the border tiles, skipping the first and last row and column
@@ -64,14 +97,42 @@ describe("solciv", () => {
 
   const playerLocation = getRandomCoordinates();
 
+  it("Should not initialize game with unknown terrain", async () => {
+    const invalidMap = Array.from({ length: 400 }, () => 10);
+    const accounts = {
+      registry: registryKey,
+      game: gameKey,
+      player: provider.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    try {
+      await program.methods.initializeGame(invalidMap, 1).accounts(accounts).rpc();
+      expect.fail("map with unknown terrain should be rejected");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("InvalidTerrain");
+    }
+  });
+
   it("Initialize game", async () => {
     // generate random 20x20 map with tile types from 1 to 9
     const randomMap = Array.from({ length: 400 }, () => Math.floor(Math.random() * 9) + 1);
 
+    // make the area around the player passable plains ("6"), so the units can move freely
+    for (let y = playerLocation.y - 4; y <= playerLocation.y + 4; y++) {
+      for (let x = playerLocation.x - 4; x <= playerLocation.x + 4; x++) {
+        if (x >= 0 && x < 20 && y >= 0 && y < 20) {
+          randomMap[x + y * 20] = 6;
+        }
+      }
+    }
     // "6" value is the land type that can be upgraded to "Farm"
     randomMap[playerLocation.x + playerLocation.y * 20 + 1] = 6;
+    // "9" is water that units cannot enter, placed next to the warrior
+    randomMap[playerLocation.x - 1 + (playerLocation.y + 1) * 20] = 9;
 
     const accounts = {
+      registry: registryKey,
       game: gameKey,
       player: provider.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId,
@@ -81,6 +142,11 @@ describe("solciv", () => {
 
     expect(account.player.toBase58()).equal(provider.publicKey.toBase58());
     expect(account.map.length).equal(randomMap.length);
+    expect(account.gameId.toNumber()).equal(0);
+
+    const registry = await program.account.gameRegistry.fetch(registryKey);
+    expect(registry.nextGameId.toNumber()).equal(1);
+    expect(registry.games.map((game) => game.toBase58())).deep.equal([gameKey.toBase58()]);
   });
 
   it("Initialize player with units and balances", async () => {
@@ -96,11 +162,10 @@ describe("solciv", () => {
     expect(account.units.length).equal(3);
     expect(account.nextUnitId).equal(3);
 
-    // Fetch the game account to check if the initial position set to 'discovered'
-    const gameAccount = await program.account.game.fetch(gameKey);
+    // the initial position is discovered by the player
     const MAP_BOUND = 20;
     const tileIndex = position.y * MAP_BOUND + position.x;
-    expect(gameAccount.map[tileIndex].discovered).to.be.true;
+    expect(account.discoveredTiles[Math.floor(tileIndex / 8)] & (1 << tileIndex % 8)).not.equal(0);
   });
 
   it("Initialize NPC with units and cities", async () => {
@@ -131,6 +196,280 @@ describe("solciv", () => {
     expect(account.nextCityId).equal(2);
   });
 
+  const secondPlayer = anchor.web3.Keypair.generate();
+
+  const [secondPlayerKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("PLAYER"), gameKey.toBuffer(), secondPlayer.publicKey.toBuffer()],
+    program.programId
+  );
+
+  const [secondRegistryKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("REGISTRY"), secondPlayer.publicKey.toBuffer()],
+    program.programId
+  );
+
+  it("Second player joins the game", async () => {
+    const signature = await provider.connection.requestAirdrop(
+      secondPlayer.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(signature);
+
+    const accounts = {
+      game: gameKey,
+      playerAccount: secondPlayerKey,
+      registry: secondRegistryKey,
+      player: secondPlayer.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    const position = getRandomCoordinates();
+    await program.methods.joinGame(position).accounts(accounts).signers([secondPlayer]).rpc();
+
+    const gameAccount = await program.account.game.fetch(gameKey);
+    expect(gameAccount.players.length).equal(2);
+    expect(gameAccount.players[1].toBase58()).equal(secondPlayer.publicKey.toBase58());
+    const account = await program.account.player.fetch(secondPlayerKey);
+    expect(account.units.length).equal(3);
+  });
+
+  it("Should not end turn out of order", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: secondPlayerKey,
+      player: secondPlayer.publicKey,
+      npcAccount: npcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    try {
+      await program.methods.endTurn().accounts(accounts).signers([secondPlayer]).rpc();
+      expect.fail("second player should wait for the host to end the turn");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotYourTurn");
+    }
+  });
+
+  it("Should not move units during the turn of another player", async () => {
+    const account = await program.account.player.fetch(secondPlayerKey);
+    const warrior = account.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods
+        .moveUnit(warrior.unitId, warrior.x, warrior.y)
+        .accounts({
+          game: gameKey,
+          playerAccount: secondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: npcKey,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: playerKey, isWritable: false, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("second player should wait for the host to end the turn");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotYourTurn");
+    }
+  });
+
+  it("Should not skip the turn of the host before the timeout", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: secondPlayerKey,
+      skippedAccount: playerKey,
+      player: secondPlayer.publicKey,
+    };
+    try {
+      await program.methods.skipTurn().accounts(accounts).signers([secondPlayer]).rpc();
+      expect.fail("the host still has time to end the turn");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TurnNotTimedOut");
+    }
+  });
+
+  it("Declare war and sign peace with the second player", async () => {
+    await program.methods
+      .declareWar(secondPlayer.publicKey)
+      .accounts({ game: gameKey, playerAccount: playerKey, player: provider.publicKey })
+      .rpc();
+    let gameAccount = await program.account.game.fetch(gameKey);
+    expect(gameAccount.relations[0].status).deep.equal({ war: {} });
+
+    // the second player answers during its own turn, the host accepts in the next round
+    await commitAndReveal(playerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: gameKey,
+          playerAccount: playerKey,
+          player: provider.publicKey,
+          npcAccount: npcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .rpc()
+    );
+    await program.methods
+      .proposePeace(provider.publicKey)
+      .accounts({ game: gameKey, playerAccount: secondPlayerKey, player: secondPlayer.publicKey })
+      .signers([secondPlayer])
+      .rpc();
+    await commitAndReveal(secondPlayerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: gameKey,
+          playerAccount: secondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: npcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: playerKey, isWritable: true, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc()
+    );
+    await program.methods
+      .acceptPeace(secondPlayer.publicKey)
+      .accounts({ game: gameKey, playerAccount: playerKey, player: provider.publicKey })
+      .rpc();
+    gameAccount = await program.account.game.fetch(gameKey);
+    expect(gameAccount.relations[0].status).deep.equal({ peace: {} });
+    expect(gameAccount.relations[0].proposal).equal(null);
+  });
+
+  it("Should not accept alliance without a proposal", async () => {
+    try {
+      await program.methods
+        .acceptAlliance()
+        .accounts({
+          game: gameKey,
+          playerAccount: playerKey,
+          allyAccount: secondPlayerKey,
+          player: provider.publicKey,
+        })
+        .rpc();
+      expect.fail("alliance should be proposed first");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NoProposal");
+    }
+  });
+
+  it("Allies share the tiles they discover after the alliance", async () => {
+    await program.methods
+      .proposeAlliance(secondPlayer.publicKey)
+      .accounts({ game: gameKey, playerAccount: playerKey, player: provider.publicKey })
+      .rpc();
+    await commitAndReveal(playerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: gameKey,
+          playerAccount: playerKey,
+          player: provider.publicKey,
+          npcAccount: npcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .rpc()
+    );
+    await program.methods
+      .acceptAlliance()
+      .accounts({
+        game: gameKey,
+        playerAccount: secondPlayerKey,
+        allyAccount: playerKey,
+        player: secondPlayer.publicKey,
+      })
+      .signers([secondPlayer])
+      .rpc();
+
+    // the warrior of the second player discovers the tiles around it again, the host gets them too
+    const account = await program.account.player.fetch(secondPlayerKey);
+    const warrior = account.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const moveAccounts = {
+      game: gameKey,
+      playerAccount: secondPlayerKey,
+      player: secondPlayer.publicKey,
+      npcAccount: npcKey,
+      sessionToken: null,
+    };
+    try {
+      await program.methods
+        .moveUnit(warrior.unitId, warrior.x, warrior.y)
+        .accounts(moveAccounts)
+        .remainingAccounts([{ pubkey: playerKey, isWritable: false, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("the account of the ally should be writable");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("InvalidPlayerAccounts");
+    }
+    await program.methods
+      .moveUnit(warrior.unitId, warrior.x, warrior.y)
+      .accounts(moveAccounts)
+      .remainingAccounts([{ pubkey: playerKey, isWritable: true, isSigner: false }])
+      .signers([secondPlayer])
+      .rpc();
+    const hostAccount = await program.account.player.fetch(playerKey);
+    const tileIndex = warrior.y * 20 + warrior.x;
+    expect(hostAccount.discoveredTiles[Math.floor(tileIndex / 8)] & (1 << tileIndex % 8)).not.equal(0);
+
+    await commitAndReveal(secondPlayerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: gameKey,
+          playerAccount: secondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: npcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: playerKey, isWritable: true, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc()
+    );
+  });
+
+  it("Should not close the game while other players are joined", async () => {
+    const accounts = {
+      game: gameKey,
+      npcAccount: npcKey,
+      playerAccount: playerKey,
+      registry: registryKey,
+      player: provider.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    try {
+      await program.methods.closeGame().accounts(accounts).rpc();
+      expect.fail("second player is still in the game");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("PlayersStillJoined");
+    }
+  });
+
+  it("Second player leaves the game", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: secondPlayerKey,
+      registry: secondRegistryKey,
+      player: secondPlayer.publicKey,
+    };
+    await program.methods.leaveGame().accounts(accounts).signers([secondPlayer]).rpc();
+
+    const gameAccount = await program.account.game.fetch(gameKey);
+    expect(gameAccount.players.length).equal(1);
+    expect(gameAccount.currentPlayerIndex).equal(0);
+    const registry = await program.account.gameRegistry.fetch(secondRegistryKey);
+    expect(registry.games.length).equal(0);
+  });
+
   it("Should attack barbarian", async () => {
     return;
     const accounts = {
@@ -138,21 +477,48 @@ describe("solciv", () => {
       playerAccount: playerKey,
       npcAccount: npcKey,
       player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
     };
     const unitId = 2;
     const barbarianId = 2;
-    const tx = await program.methods.attackUnit(unitId, barbarianId).accounts(accounts).rpc();
+    await commitAndReveal(playerKey, () => program.methods.attackUnit(unitId, barbarianId).accounts(accounts).rpc());
     const account = await program.account.npc.fetch(npcKey);
     const playerData = await program.account.player.fetch(playerKey);
     expect(account.units[barbarianId].health).lessThan(100);
     expect(playerData.units[unitId].health).lessThan(100);
   });
 
+  it("Preview attack against barbarian unit", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      npcAccount: npcKey,
+      defenderAccount: null,
+    };
+    // warrior against warrior: 30 damage scaled by the random multiplier in [0.9, ~1.1],
+    // the barbarian may get a defense bonus from the terrain of its tile
+    const preview = await program.methods
+      .previewAttack(2, { unit: { 0: 0 } })
+      .accounts(accounts)
+      .view();
+    expect(preview.minGivenDamage).lessThanOrEqual(preview.maxGivenDamage);
+    expect(preview.maxGivenDamage).lessThanOrEqual(33);
+    expect(preview.minTakenDamage).greaterThanOrEqual(27);
+    expect(preview.minTakenDamage).lessThanOrEqual(preview.maxTakenDamage);
+    expect(preview.attackerMayDie).equal(false);
+    expect(preview.defenderMayDie).equal(false);
+    const npcAccount = await program.account.npc.fetch(npcKey);
+    expect(npcAccount.units[0].health).equal(100);
+  });
+
   it("Move unit", async () => {
     const accounts = {
       game: gameKey,
       playerAccount: playerKey,
       player: provider.publicKey,
+      npcAccount: npcKey,
+      sessionToken: null,
     };
 
     // get player account and find unit of type "settler"
@@ -172,6 +538,8 @@ describe("solciv", () => {
       game: gameKey,
       playerAccount: playerKey,
       player: provider.publicKey,
+      npcAccount: npcKey,
+      sessionToken: null,
     };
     const prevState = await program.account.player.fetch(playerKey);
     const unit = prevState.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
@@ -194,6 +562,127 @@ describe("solciv", () => {
       const { message } = e;
       expect(message).include("OutOfMovementRange");
     }
+    // Cannot enter water
+    try {
+      await program.methods
+        .moveUnit(unitId, unit.x - 1, unit.y)
+        .accounts(accounts)
+        .rpc();
+      expect.fail("units should not enter water tiles");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("ImpassableTerrain");
+    }
+  });
+
+  it("Should not heal unit with full health", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    const playerAccount = await program.account.player.fetch(playerKey);
+    const unit = playerAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods.healUnit(unit.unitId).accounts(accounts).rpc();
+      expect.fail("unit with full health should not be healed");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("UnitNotDamaged");
+    }
+  });
+
+  it("Should fortify military units only", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    let playerAccount = await program.account.player.fetch(playerKey);
+    const builder = playerAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    try {
+      await program.methods.setStance(builder.unitId, { fortified: {} }).accounts(accounts).rpc();
+      expect.fail("civilian units should not fortify");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("InvalidUnitType");
+    }
+    const warrior = playerAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    await program.methods.setStance(warrior.unitId, { fortified: {} }).accounts(accounts).rpc();
+    playerAccount = await program.account.player.fetch(playerKey);
+    const fortified = playerAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(Object.keys(fortified.stance)[0]).equal("fortified");
+    expect(fortified.fortifyTurns).equal(0);
+  });
+
+  it("Should not promote unit without enough experience", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    const playerAccount = await program.account.player.fetch(playerKey);
+    const warrior = playerAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods.upgradeUnit(warrior.unitId, { strength: {} }).accounts(accounts).rpc();
+      expect.fail("unit without enough experience should not be promoted");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotEnoughExp");
+    }
+  });
+
+  it("Should not upgrade unit type without technology", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    const playerAccount = await program.account.player.fetch(playerKey);
+    const warrior = playerAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods.upgradeUnitType(warrior.unitId).accounts(accounts).rpc();
+      expect.fail("warrior should not be upgraded before Iron Working is researched");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TechnologyNotResearched");
+    }
+  });
+
+  it("Should not disband unknown unit", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    try {
+      await program.methods.disbandUnit(999).accounts(accounts).rpc();
+      expect.fail("unknown unit should not be disbanded");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("UnitNotFound");
+    }
+  });
+
+  it("Should stack only a civilian with a military unit", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      player: provider.publicKey,
+      npcAccount: npcKey,
+      sessionToken: null,
+    };
+    const prevState = await program.account.player.fetch(playerKey);
+    const settler = prevState.units.find((unit) => Object.keys(unit.unitType)[0] === "settler");
+    const builder = prevState.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    const warrior = prevState.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods.moveUnit(builder.unitId, settler.x, settler.y).accounts(accounts).rpc();
+      expect.fail("two civilian units should not share a tile");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TileOccupied");
+    }
+    await program.methods.moveUnit(warrior.unitId, builder.x, builder.y).accounts(accounts).rpc();
+    const account = await program.account.player.fetch(playerKey);
+    const stacked = account.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(stacked.x).equal(builder.x);
+    expect(stacked.y).equal(builder.y);
   });
 
   it("Found the city", async () => {
@@ -221,6 +710,42 @@ describe("solciv", () => {
     expect(city.y).equal(unit.y);
     expect(city.cityId).equal(0);
     expect(city.name).equal(name);
+    // city founded on plains gets +1 food and +1 production from its center tile
+    expect(city.foodYield).equal(3);
+    expect(city.productionYield).equal(3);
+  });
+
+  it("Should not strike from a city without walls", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      npcAccount: npcKey,
+      defenderAccount: null,
+      player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    try {
+      await program.methods.cityStrike(0, 0).accounts(accounts).rpc();
+      expect.fail("city without walls should not strike");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NoWall");
+    }
+  });
+
+  it("Should not raze a city that was not captured", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+      player: provider.publicKey,
+    };
+    try {
+      await program.methods.razeCity(0).accounts(accounts).rpc();
+      expect.fail("own city should not be razed");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotOccupied");
+    }
   });
 
   it("Should add building to production queue", async () => {
@@ -267,6 +792,18 @@ describe("solciv", () => {
     }
   });
 
+  it("Should not add pikeman to production queue: TechnologyNotResearched", async () => {
+    const cityId = 0;
+    const productionItem = { unit: { "0": { pikeman: {} } } };
+    try {
+      await addToProductionQueue(cityId, productionItem);
+      expect.fail("pikeman requires Medieval Warfare");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TechnologyNotResearched");
+    }
+  });
+
   it("Should add 4 more items to production queue", async () => {
     const cityId = 0;
     const items = [
@@ -445,32 +982,75 @@ describe("solciv", () => {
 
   it("End 25 turns", async () => {
     const prevPlayerAccount = await program.account.player.fetch(playerKey);
+    const prevGameAccount = await program.account.game.fetch(gameKey);
     const accounts = {
       game: gameKey,
       playerAccount: playerKey,
       player: provider.publicKey,
       npcAccount: npcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
     };
 
     for (let i = 1; i <= 25; i++) {
-      await program.methods.endTurn().accounts(accounts).rpc();
+      await commitAndReveal(playerKey, () => program.methods.endTurn().accounts(accounts).rpc());
     }
     const account = await program.account.game.fetch(gameKey);
     expect(account.turn).greaterThan(1);
+    // every turn mixes a fresh slot hash into the game seed
+    expect(account.randomness.seed).not.deep.equal(prevGameAccount.randomness.seed);
     const playerAccount = await program.account.player.fetch(playerKey);
     expect(playerAccount.resources.gold).greaterThan(prevPlayerAccount.resources.gold);
+    expect(playerAccount.resources.food).to.be.at.most(100);
   });
 
-  it("Should not start research of already unlocked technology", async () => {
+  it("Should not move units while the end of turn is pending", async function() {
     const accounts = {
+      game: gameKey,
       playerAccount: playerKey,
+      player: provider.publicKey,
+      npcAccount: npcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
     };
-    const technology = { writing: {} };
+    await program.methods.endTurn().accounts(accounts).rpc();
+    if (!(await waitForReveal(playerKey))) {
+      console.warn("Skipping the pending action test, actions resolve at once with the stub-randomness feature");
+      return this.skip();
+    }
+    const prevState = await program.account.player.fetch(playerKey);
+    const warrior = prevState.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
     try {
-      await program.methods.startResearch(technology).accounts(accounts).rpc();
+      await program.methods
+        .moveUnit(warrior.unitId, warrior.x, warrior.y - 1)
+        .accounts({
+          game: gameKey,
+          playerAccount: playerKey,
+          player: provider.publicKey,
+          npcAccount: npcKey,
+          sessionToken: null,
+        })
+        .rpc();
+      expect.fail("units should not move while the end of turn is pending");
     } catch (e) {
       const { message } = e;
-      expect(message).include("ResearchAlreadyCompleted");
+      expect(message).include("CommitmentPending");
+    }
+    await program.methods.endTurn().accounts(accounts).rpc();
+    const account = await program.account.player.fetch(playerKey);
+    expect(account.pendingCommitment).equal(null);
+  });
+
+  it("Should not start research of already unlocked technology", async () => {
+    const accounts = {
+      playerAccount: playerKey,
+    };
+    const technology = { writing: {} };
+    try {
+      await program.methods.startResearch(technology).accounts(accounts).rpc();
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("ResearchAlreadyCompleted");
     }
   });
 
@@ -509,6 +1089,86 @@ describe("solciv", () => {
     expect(player.resources.gold).lessThan(prevState.resources.gold);
   });
 
+  const sessionKey = anchor.web3.Keypair.generate();
+
+  const [sessionTokenKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("SESSION"), playerKey.toBuffer(), sessionKey.publicKey.toBuffer()],
+    program.programId
+  );
+
+  it("Create session key", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      sessionToken: sessionTokenKey,
+      player: provider.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    await program.methods.createSession(sessionKey.publicKey, 5).accounts(accounts).rpc();
+
+    const gameAccount = await program.account.game.fetch(gameKey);
+    const sessionToken = await program.account.sessionToken.fetch(sessionTokenKey);
+    expect(sessionToken.sessionKey.toBase58()).equal(sessionKey.publicKey.toBase58());
+    expect(sessionToken.expiresAtTurn).equal(gameAccount.turn + 5);
+  });
+
+  it("End turn with session key", async () => {
+    const signature = await provider.connection.requestAirdrop(
+      sessionKey.publicKey,
+      anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(signature);
+
+    const prevGameAccount = await program.account.game.fetch(gameKey);
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      npcAccount: npcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: sessionTokenKey,
+      player: sessionKey.publicKey,
+    };
+    await commitAndReveal(playerKey, () =>
+      program.methods.endTurn().accounts(accounts).signers([sessionKey]).rpc()
+    );
+
+    const gameAccount = await program.account.game.fetch(gameKey);
+    expect(gameAccount.turn).equal(prevGameAccount.turn + 1);
+  });
+
+  it("Should not end turn with session key without session token", async () => {
+    const accounts = {
+      game: gameKey,
+      playerAccount: playerKey,
+      npcAccount: npcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+      player: sessionKey.publicKey,
+    };
+    try {
+      await program.methods.endTurn().accounts(accounts).signers([sessionKey]).rpc();
+      expect.fail("session key should not act without a session token");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("InvalidSigner");
+    }
+  });
+
+  it("Revoke session key", async () => {
+    const accounts = {
+      sessionToken: sessionTokenKey,
+      player: provider.publicKey,
+    };
+    await program.methods.revokeSession().accounts(accounts).rpc();
+    try {
+      await program.account.sessionToken.fetch(sessionTokenKey);
+      expect.fail("session token should be closed");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("Account does not exist or has no data");
+    }
+  });
+
   it("Should check if barbarians were spawned", async () => {
     const npcAccount = await program.account.npc.fetch(npcKey);
     expect(npcAccount.units.length).greaterThanOrEqual(2);
@@ -593,6 +1253,7 @@ describe("solciv", () => {
       game: gameKey,
       npcAccount: npcKey,
       playerAccount: playerKey,
+      registry: registryKey,
       player: provider.publicKey,
       systemProgram: anchor.web3.SystemProgram.programId,
     };
@@ -602,6 +1263,9 @@ describe("solciv", () => {
     const balance = await provider.connection.getBalance(provider.publicKey);
     // verify that rent was returned
     expect(balance).greaterThan(prevBalance);
+    // verify that the game was removed from the registry
+    const registry = await program.account.gameRegistry.fetch(registryKey);
+    expect(registry.games.length).equal(0);
     // verify that all accounts were closed
     try {
       await program.account.game.fetch(gameKey);
@@ -622,4 +1286,817 @@ describe("solciv", () => {
       expect(message).include("Account does not exist or has no data");
     }
   });
+
+  // second game of the wallet, where the second player joins next to the host to fight a war
+  const warGameId = new anchor.BN(1);
+
+  const [warGameKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("GAME"), provider.publicKey.toBuffer(), warGameId.toArrayLike(Buffer, "le", 8)],
+    program.programId
+  );
+
+  const [warPlayerKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("PLAYER"), warGameKey.toBuffer(), provider.publicKey.toBuffer()],
+    program.programId
+  );
+
+  const [warSecondPlayerKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("PLAYER"), warGameKey.toBuffer(), secondPlayer.publicKey.toBuffer()],
+    program.programId
+  );
+
+  const [warNpcKey] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("NPC"), warGameKey.toBuffer()],
+    program.programId
+  );
+
+  // host units: settler (4, 8), builder (5, 8), warrior (4, 9)
+  // second player units: settler (6, 8), builder (7, 8), warrior (6, 9)
+  const hostLocation = { x: 4, y: 8 };
+  const enemyLocation = { x: 6, y: 8 };
+  const barbarianLocations = [
+    { x: 17, y: 17 },
+    { x: 17, y: 2 },
+  ];
+
+  it("Initialize a game for the war between players", async () => {
+    // plains everywhere, the barbarian villages are surrounded by water, so barbarians never reach the players
+    const map = Array.from({ length: 400 }, () => 6);
+    for (const village of barbarianLocations) {
+      for (let y = village.y - 2; y <= village.y + 2; y++) {
+        for (let x = village.x - 2; x <= village.x + 2; x++) {
+          const onRing = Math.max(Math.abs(x - village.x), Math.abs(y - village.y)) === 2;
+          if (onRing && x >= 0 && x < 20 && y >= 0 && y < 20) {
+            map[x + y * 20] = 9;
+          }
+        }
+      }
+    }
+
+    await program.methods
+      .initializeGame(map, 0)
+      .accounts({
+        registry: registryKey,
+        game: warGameKey,
+        player: provider.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods
+      .initializePlayer(hostLocation)
+      .accounts({
+        game: warGameKey,
+        playerAccount: warPlayerKey,
+        player: provider.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+    await program.methods
+      .initializeNpc(barbarianLocations[0], barbarianLocations[1])
+      .accounts({
+        game: warGameKey,
+        npcAccount: warNpcKey,
+        player: provider.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    const gameAccount = await program.account.game.fetch(warGameKey);
+    expect(gameAccount.gameId.toNumber()).equal(1);
+    expect(gameAccount.players.length).equal(1);
+  });
+
+  // the host plays the war game alone until the condition holds, the second player joins afterwards
+  async function endWarGameTurnsUntil(condition: (account) => boolean) {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      player: provider.publicKey,
+      npcAccount: warNpcKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    for (let turn = 0; turn < 100; turn++) {
+      await commitAndReveal(warPlayerKey, () => program.methods.endTurn().accounts(accounts).rpc());
+      const account = await program.account.player.fetch(warPlayerKey);
+      if (condition(account)) {
+        return account;
+      }
+    }
+    expect.fail("condition was not met within 100 turns");
+  }
+
+  async function researchInWarGame(technology) {
+    await program.methods.startResearch(technology).accounts({ playerAccount: warPlayerKey }).rpc();
+    const name = Object.keys(technology)[0];
+    await endWarGameTurnsUntil((account) =>
+      account.researchedTechnologies.some((tech) => Object.keys(tech)[0] === name)
+    );
+  }
+
+  async function trainInWarGame(unitType) {
+    const accounts = {
+      game: warGameKey,
+      player: provider.publicKey,
+      playerAccount: warPlayerKey,
+      sessionToken: null,
+    };
+    await program.methods.addToProductionQueue(0, { unit: { "0": unitType } }).accounts(accounts).rpc();
+    const name = Object.keys(unitType)[0];
+    return endWarGameTurnsUntil((account) => account.units.some((unit) => Object.keys(unit.unitType)[0] === name));
+  }
+
+  // the host ends the turn, the second player moves next
+  async function endHostWarTurn() {
+    await commitAndReveal(warPlayerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: warGameKey,
+          playerAccount: warPlayerKey,
+          player: provider.publicKey,
+          npcAccount: warNpcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .rpc()
+    );
+  }
+
+  // the second player ends the round, passing the host account for the barbarians' turn
+  async function endSecondWarTurn() {
+    await commitAndReveal(warSecondPlayerKey, () =>
+      program.methods
+        .endTurn()
+        .accounts({
+          game: warGameKey,
+          playerAccount: warSecondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: warNpcKey,
+          recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: warPlayerKey, isWritable: true, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc()
+    );
+  }
+
+  // both players end their turns, so their units can move and attack again
+  async function endWarGameRound() {
+    await endHostWarTurn();
+    await endSecondWarTurn();
+  }
+
+  it("Host founds a city and trains an archer", async () => {
+    const accounts = {
+      game: warGameKey,
+      player: provider.publicKey,
+      playerAccount: warPlayerKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const settler = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "settler");
+    await program.methods.foundCity(settler.x, settler.y, settler.unitId, "Host City").accounts(accounts).rpc();
+
+    await researchInWarGame({ animalHusbandry: {} });
+    await researchInWarGame({ archery: {} });
+    const account = await trainInWarGame({ archer: {} });
+    const archer = account.units.find((unit) => Object.keys(unit.unitType)[0] === "archer");
+    expect(archer.x).equal(hostLocation.x);
+    expect(archer.y).equal(hostLocation.y);
+  });
+
+  it("Host trains a catapult", async () => {
+    await researchInWarGame({ horsebackRiding: {} });
+    await researchInWarGame({ ironWorking: {} });
+    const account = await trainInWarGame({ catapult: {} });
+    const catapult = account.units.find((unit) => Object.keys(unit.unitType)[0] === "catapult");
+    expect(catapult.x).equal(hostLocation.x);
+    expect(catapult.y).equal(hostLocation.y);
+  });
+
+  it("Disband units inside and outside a city", async () => {
+    const accounts = {
+      playerAccount: warPlayerKey,
+      player: provider.publicKey,
+    };
+    // a spearman disbanded in the city adds half of its 25 production to the city
+    let prevAccount = await trainInWarGame({ spearman: {} });
+    let spearman = prevAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "spearman");
+    await program.methods.disbandUnit(spearman.unitId).accounts(accounts).rpc();
+    let account = await program.account.player.fetch(warPlayerKey);
+    expect(account.units.some((unit) => unit.unitId === spearman.unitId)).equal(false);
+    expect(account.cities[0].accumulatedProduction).equal(prevAccount.cities[0].accumulatedProduction + 12);
+    expect(account.resources.gold).equal(prevAccount.resources.gold);
+
+    // a spearman costs no iron or horses, so nothing is returned outside the city
+    await trainInWarGame({ spearman: {} });
+    prevAccount = await program.account.player.fetch(warPlayerKey);
+    spearman = prevAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "spearman");
+    await program.methods
+      .moveUnit(spearman.unitId, spearman.x - 1, spearman.y)
+      .accounts({
+        game: warGameKey,
+        playerAccount: warPlayerKey,
+        player: provider.publicKey,
+        npcAccount: warNpcKey,
+        sessionToken: null,
+      })
+      .rpc();
+    await program.methods.disbandUnit(spearman.unitId).accounts(accounts).rpc();
+    account = await program.account.player.fetch(warPlayerKey);
+    expect(account.units.some((unit) => unit.unitId === spearman.unitId)).equal(false);
+    expect(account.cities[0].accumulatedProduction).equal(prevAccount.cities[0].accumulatedProduction);
+    expect(account.resources.gold).equal(prevAccount.resources.gold);
+    expect(account.resources.iron).equal(prevAccount.resources.iron);
+    expect(account.resources.horses).equal(prevAccount.resources.horses);
+  });
+
+  it("Second player joins the war game", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      registry: secondRegistryKey,
+      player: secondPlayer.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    await program.methods.joinGame(enemyLocation).accounts(accounts).signers([secondPlayer]).rpc();
+
+    const gameAccount = await program.account.game.fetch(warGameKey);
+    expect(gameAccount.players.length).equal(2);
+  });
+
+  it("Second player founds a city", async () => {
+    await endHostWarTurn();
+    const accounts = {
+      game: warGameKey,
+      player: secondPlayer.publicKey,
+      playerAccount: warSecondPlayerKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const settler = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "settler");
+    await program.methods
+      .foundCity(settler.x, settler.y, settler.unitId, "Enemy City")
+      .accounts(accounts)
+      .remainingAccounts([{ pubkey: warPlayerKey, isWritable: false, isSigner: false }])
+      .signers([secondPlayer])
+      .rpc();
+    const account = await program.account.player.fetch(warSecondPlayerKey);
+    expect(account.cities.length).equal(1);
+  });
+
+  it("Should not attack a unit of a civilization at peace", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const target = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    try {
+      await program.methods
+        .attackPlayerUnit(warrior.unitId, target.unitId)
+        .accounts(accounts)
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("civilizations at peace should not fight");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotAtWar");
+    }
+  });
+
+  it("Should not attack own unit", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warSecondPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const builder = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    try {
+      await program.methods
+        .attackPlayerUnit(warrior.unitId, builder.unitId)
+        .accounts(accounts)
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("player should not attack own units");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("InvalidAttack");
+    }
+  });
+
+  it("Should not move into a unit of a civilization at peace", async () => {
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const builder = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    try {
+      await program.methods
+        .moveUnit(warrior.unitId, builder.x, builder.y)
+        .accounts({
+          game: warGameKey,
+          playerAccount: warSecondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: warNpcKey,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: warPlayerKey, isWritable: false, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("units should not stop on a unit of another civilization");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TileOccupied");
+    }
+  });
+
+  it("Second player declares war on the host", async () => {
+    await program.methods
+      .declareWar(provider.publicKey)
+      .accounts({ game: warGameKey, playerAccount: warSecondPlayerKey, player: secondPlayer.publicKey })
+      .signers([secondPlayer])
+      .rpc();
+    const gameAccount = await program.account.game.fetch(warGameKey);
+    expect(gameAccount.relations[0].status).deep.equal({ war: {} });
+  });
+
+  it("Should not move into a unit of a civilization at war", async () => {
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const builder = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    try {
+      await program.methods
+        .moveUnit(warrior.unitId, builder.x, builder.y)
+        .accounts({
+          game: warGameKey,
+          playerAccount: warSecondPlayerKey,
+          player: secondPlayer.publicKey,
+          npcAccount: warNpcKey,
+          sessionToken: null,
+        })
+        .remainingAccounts([{ pubkey: warPlayerKey, isWritable: false, isSigner: false }])
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("units of a civilization at war should block the tile");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("TileOccupied");
+    }
+  });
+
+  it("Attack a city of a civilization at war", async () => {
+    await endSecondWarTurn();
+    let hostAccount = await program.account.player.fetch(warPlayerKey);
+    const warrior = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    // step next to the enemy city
+    await program.methods
+      .moveUnit(warrior.unitId, warrior.x + 1, warrior.y)
+      .accounts({
+        game: warGameKey,
+        playerAccount: warPlayerKey,
+        player: provider.publicKey,
+        npcAccount: warNpcKey,
+        sessionToken: null,
+      })
+      .remainingAccounts([{ pubkey: warSecondPlayerKey, isWritable: false, isSigner: false }])
+      .rpc();
+
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      defenderAccount: warSecondPlayerKey,
+      player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    await commitAndReveal(warPlayerKey, () =>
+      program.methods.attackPlayerCity(warrior.unitId, 0).accounts(accounts).rpc()
+    );
+
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    expect(enemyAccount.cities[0].health).lessThan(100);
+    hostAccount = await program.account.player.fetch(warPlayerKey);
+    const attacker = hostAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(attacker.health).lessThan(100);
+    expect(attacker.movementRange).equal(0);
+  });
+
+  it("Attack a unit of a civilization at war", async () => {
+    await endHostWarTurn();
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const prevHostAccount = await program.account.player.fetch(warPlayerKey);
+    const target = prevHostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    await commitAndReveal(warSecondPlayerKey, () =>
+      program.methods
+        .attackPlayerUnit(warrior.unitId, target.unitId)
+        .accounts(accounts)
+        .signers([secondPlayer])
+        .rpc()
+    );
+
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const attacker = enemyAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(attacker.health).lessThan(warrior.health);
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const defender = hostAccount.units.find((unit) => unit.unitId === target.unitId);
+    expect(defender.health).lessThan(target.health);
+  });
+
+  it("Siege unit is stronger against cities and weaker against units", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      npcAccount: null,
+      defenderAccount: warSecondPlayerKey,
+    };
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const archer = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "archer");
+    const catapult = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "catapult");
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const preview = (attacker, target) =>
+      program.methods.previewAttack(attacker.unitId, target).accounts(accounts).view();
+
+    // catapult (16 - 10 penalty) hits a warrior softer than an archer (10)
+    const archerVsUnit = await preview(archer, { unit: { 0: warrior.unitId } });
+    const catapultVsUnit = await preview(catapult, { unit: { 0: warrior.unitId } });
+    expect(catapultVsUnit.maxGivenDamage).lessThan(archerVsUnit.maxGivenDamage);
+    expect(catapultVsUnit.maxTakenDamage).equal(0);
+
+    // catapult (16 + 10 bonus) against a city without walls deals at least 38 damage,
+    // it would deal at most 31 without the bonus
+    const archerVsCity = await preview(archer, { city: { 0: 0 } });
+    const catapultVsCity = await preview(catapult, { city: { 0: 0 } });
+    expect(catapultVsCity.minGivenDamage).greaterThan(31);
+    expect(catapultVsCity.minGivenDamage).greaterThan(archerVsCity.maxGivenDamage);
+  });
+
+  it("Ranged unit takes no counter-damage", async () => {
+    await endSecondWarTurn();
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      defenderAccount: warSecondPlayerKey,
+      player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const prevHostAccount = await program.account.player.fetch(warPlayerKey);
+    const archer = prevHostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "archer");
+    const prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const target = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    // the archer shoots from the host city, 2 tiles away from the enemy warrior
+    await commitAndReveal(warPlayerKey, () =>
+      program.methods.attackPlayerUnit(archer.unitId, target.unitId).accounts(accounts).rpc()
+    );
+
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const attacker = hostAccount.units.find((unit) => unit.unitId === archer.unitId);
+    expect(attacker.health).equal(archer.health);
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const defender = enemyAccount.units.find((unit) => unit.unitId === target.unitId);
+    expect(defender.health).lessThan(target.health);
+  });
+
+  it("Capture a lone civilian unit", async () => {
+    await endHostWarTurn();
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const prevHostAccount = await program.account.player.fetch(warPlayerKey);
+    const builder = prevHostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "builder");
+    await commitAndReveal(warSecondPlayerKey, () =>
+      program.methods
+        .attackPlayerUnit(warrior.unitId, builder.unitId)
+        .accounts(accounts)
+        .signers([secondPlayer])
+        .rpc()
+    );
+
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    expect(hostAccount.units.some((unit) => unit.unitId === builder.unitId)).equal(false);
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    expect(enemyAccount.units.length).equal(prevEnemyAccount.units.length + 1);
+    const captured = enemyAccount.units[enemyAccount.units.length - 1];
+    expect(Object.keys(captured.unitType)[0]).equal("builder");
+    expect(captured.player.toBase58()).equal(secondPlayer.publicKey.toBase58());
+    expect(captured.x).equal(builder.x);
+    expect(captured.y).equal(builder.y);
+  });
+
+  it("Heal a unit with the food of the city next to it", async () => {
+    const moveAccounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      player: secondPlayer.publicKey,
+      npcAccount: warNpcKey,
+      sessionToken: null,
+    };
+    // the warrior keeps stepping between two tiles next to the city while the city gathers food,
+    // so it doesn't heal on its own
+    let prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    for (let round = 0; prevEnemyAccount.cities[0].accumulatedFood < 10; round++) {
+      if (round === 30) {
+        expect.fail("the city did not gather enough food");
+      }
+      const warrior = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+      if (warrior.movementRange > 0) {
+        await program.methods
+          .moveUnit(warrior.unitId, warrior.x === enemyLocation.x ? enemyLocation.x + 1 : enemyLocation.x, warrior.y)
+          .accounts(moveAccounts)
+          .remainingAccounts([{ pubkey: warPlayerKey, isWritable: false, isSigner: false }])
+          .signers([secondPlayer])
+          .rpc();
+      }
+      await endSecondWarTurn();
+      await endHostWarTurn();
+      prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    }
+    // the stockpile is empty, so the food comes from the city
+    expect(prevEnemyAccount.resources.food).lessThan(10);
+
+    const warrior = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    expect(warrior.health).lessThan(100);
+    await program.methods
+      .healUnit(warrior.unitId)
+      .accounts({ playerAccount: warSecondPlayerKey, player: secondPlayer.publicKey })
+      .signers([secondPlayer])
+      .rpc();
+
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const healed = enemyAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    // the warrior stands inside the territory of the city
+    expect(healed.health).equal(Math.min(warrior.health + 25, 100));
+    expect(healed.movementRange).equal(0);
+    expect(enemyAccount.cities[0].accumulatedFood).equal(prevEnemyAccount.cities[0].accumulatedFood - 10);
+    expect(enemyAccount.resources.food).equal(prevEnemyAccount.resources.food);
+  });
+
+  it("Ranged and siege units do not capture a city at 0 HP", async () => {
+    await endSecondWarTurn();
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      defenderAccount: warSecondPlayerKey,
+      player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const archer = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "archer");
+    const catapult = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "catapult");
+    let enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    let city = enemyAccount.cities[0];
+
+    // both units shoot from the host city, the enemy city heals 5 HP at the end of every round
+    for (let round = 0; round < 5 && city.health > 0; round++) {
+      for (const attacker of [catapult, archer]) {
+        if (city.health > 0) {
+          await commitAndReveal(warPlayerKey, () =>
+            program.methods.attackPlayerCity(attacker.unitId, city.cityId).accounts(accounts).rpc()
+          );
+          enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+          city = enemyAccount.cities.find((c) => c.cityId === city.cityId);
+          expect(city).not.equal(undefined);
+        }
+      }
+      if (city.health > 0) {
+        await endWarGameRound();
+        enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+        city = enemyAccount.cities.find((c) => c.cityId === city.cityId);
+      }
+    }
+    expect(city.health).equal(0);
+    expect(enemyAccount.cities.length).equal(1);
+  });
+
+  it("Melee unit captures a city at 0 HP", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warPlayerKey,
+      defenderAccount: warSecondPlayerKey,
+      player: provider.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const prevHostAccount = await program.account.player.fetch(warPlayerKey);
+    const warrior = prevHostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    const prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const city = prevEnemyAccount.cities[0];
+    // the warrior stands next to the enemy city since the first attack on it
+    await commitAndReveal(warPlayerKey, () =>
+      program.methods.attackPlayerCity(warrior.unitId, city.cityId).accounts(accounts).rpc()
+    );
+
+    const enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    expect(enemyAccount.cities.length).equal(0);
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const captured = hostAccount.cities.find((c) => c.x === city.x && c.y === city.y);
+    expect(captured.player.toBase58()).equal(provider.publicKey.toBase58());
+    expect(Object.keys(captured.status)[0]).equal("occupied");
+    const conqueror = hostAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(conqueror.x).equal(city.x);
+    expect(conqueror.y).equal(city.y);
+  });
+
+  it("Annex an occupied city", async () => {
+    const accounts = {
+      playerAccount: warPlayerKey,
+      player: provider.publicKey,
+    };
+    const prevHostAccount = await program.account.player.fetch(warPlayerKey);
+    const occupied = prevHostAccount.cities.find((c) => Object.keys(c.status)[0] === "occupied");
+    await program.methods.annexCity(occupied.cityId).accounts(accounts).rpc();
+
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const annexed = hostAccount.cities.find((c) => c.cityId === occupied.cityId);
+    expect(Object.keys(annexed.status)[0]).equal("normal");
+    try {
+      await program.methods.razeCity(occupied.cityId).accounts(accounts).rpc();
+      expect.fail("annexed city should not be razed");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotOccupied");
+    }
+  });
+
+  it("Host warrior leaves the annexed city", async () => {
+    await endWarGameRound();
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const warrior = hostAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    await program.methods
+      .moveUnit(warrior.unitId, warrior.x, warrior.y - 1)
+      .accounts({
+        game: warGameKey,
+        playerAccount: warPlayerKey,
+        player: provider.publicKey,
+        npcAccount: warNpcKey,
+        sessionToken: null,
+      })
+      .remainingAccounts([{ pubkey: warSecondPlayerKey, isWritable: false, isSigner: false }])
+      .rpc();
+    await endHostWarTurn();
+  });
+
+  it("Should not heal a unit with no city next to it", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const annexed = hostAccount.cities.find((c) => c.x === enemyLocation.x && c.y === enemyLocation.y);
+    let enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = enemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+    // the warrior gets hurt attacking the lost city, the second player has no city left to supply the food
+    await commitAndReveal(warSecondPlayerKey, () =>
+      program.methods
+        .attackPlayerCity(warrior.unitId, annexed.cityId)
+        .accounts(accounts)
+        .signers([secondPlayer])
+        .rpc()
+    );
+    await endSecondWarTurn();
+    await endHostWarTurn();
+
+    enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    expect(enemyAccount.cities.length).equal(0);
+    expect(enemyAccount.resources.food).lessThan(10);
+    const damaged = enemyAccount.units.find((unit) => unit.unitId === warrior.unitId);
+    expect(damaged.health).lessThan(100);
+    try {
+      await program.methods
+        .healUnit(warrior.unitId)
+        .accounts({ playerAccount: warSecondPlayerKey, player: secondPlayer.publicKey })
+        .signers([secondPlayer])
+        .rpc();
+      expect.fail("unit without a city next to it should not be healed");
+    } catch (e) {
+      const { message } = e;
+      expect(message).include("NotInOwnTerritory");
+    }
+  });
+
+  it("Razed city loses a citizen every turn, yields nothing and is removed once empty", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      defenderAccount: warPlayerKey,
+      player: secondPlayer.publicKey,
+      recentSlothashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      sessionToken: null,
+    };
+    const hostAccount = await program.account.player.fetch(warPlayerKey);
+    const annexed = hostAccount.cities.find((c) => c.x === enemyLocation.x && c.y === enemyLocation.y);
+    const prevEnemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    const warrior = prevEnemyAccount.units.find((unit) => Object.keys(unit.unitType)[0] === "warrior");
+
+    // the warrior of the second player takes its city back, the city heals 5 HP at the end of every host turn
+    let enemyAccount = prevEnemyAccount;
+    for (let round = 0; round < 5 && enemyAccount.cities.length === 0; round++) {
+      await commitAndReveal(warSecondPlayerKey, () =>
+        program.methods
+          .attackPlayerCity(warrior.unitId, annexed.cityId)
+          .accounts(accounts)
+          .signers([secondPlayer])
+          .rpc()
+      );
+      enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+      if (enemyAccount.cities.length === 0) {
+        await endSecondWarTurn();
+        await endHostWarTurn();
+      }
+    }
+    expect(enemyAccount.cities.length).equal(1);
+    let city = enemyAccount.cities[0];
+    expect(Object.keys(city.status)[0]).equal("occupied");
+    expect(city.goldYield).greaterThan(0);
+
+    await program.methods
+      .razeCity(city.cityId)
+      .accounts({ playerAccount: warSecondPlayerKey, player: secondPlayer.publicKey })
+      .signers([secondPlayer])
+      .rpc();
+    enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+    expect(Object.keys(enemyAccount.cities[0].status)[0]).equal("razing");
+
+    // the second player has no other city and its warrior and builders cost no maintenance,
+    // so its gold stays the same while the city is razed
+    while (city) {
+      const prevAccount = enemyAccount;
+      await endSecondWarTurn();
+      enemyAccount = await program.account.player.fetch(warSecondPlayerKey);
+      expect(enemyAccount.resources.gold).equal(prevAccount.resources.gold);
+      const prevPopulation = city.population;
+      city = enemyAccount.cities.find((c) => c.cityId === city.cityId);
+      if (city) {
+        expect(city.population).equal(prevPopulation - 1);
+        await endHostWarTurn();
+      } else {
+        expect(prevPopulation).equal(1);
+        expect(enemyAccount.resources.gems).equal(prevAccount.resources.gems + 25);
+      }
+    }
+    expect(enemyAccount.cities.length).equal(0);
+  });
+
+  it("Second player leaves the war game", async () => {
+    const accounts = {
+      game: warGameKey,
+      playerAccount: warSecondPlayerKey,
+      registry: secondRegistryKey,
+      player: secondPlayer.publicKey,
+    };
+    await program.methods.leaveGame().accounts(accounts).signers([secondPlayer]).rpc();
+
+    const gameAccount = await program.account.game.fetch(warGameKey);
+    expect(gameAccount.players.length).equal(1);
+  });
+
+  it("Should close the war game", async () => {
+    const accounts = {
+      game: warGameKey,
+      npcAccount: warNpcKey,
+      playerAccount: warPlayerKey,
+      registry: registryKey,
+      player: provider.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    await program.methods.closeGame().accounts(accounts).rpc();
+    const registry = await program.account.gameRegistry.fetch(registryKey);
+    expect(registry.games.length).equal(0);
+  });
 });